@@ -0,0 +1,193 @@
+// Preallocated packet buffers
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/csp_buffer.c
+//
+// All buffers are allocated when the pool is created. `get()` hands one out
+// wrapped in a csp_packet_t and the buffer goes back on the free list when
+// that packet is dropped, so nothing on the send/receive path allocates.
+
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::*;
+
+struct csp_buffer_pool_inner {
+    count: usize,
+    size: usize,
+    free: Mutex<Vec<Box<[u8]>>>,
+    high_water: AtomicUsize,
+}
+
+/// Fixed pool of `count` packet buffers of `size` payload bytes each.
+///
+/// Cloning the pool gives another handle to the same buffers.
+#[derive(Clone)]
+pub struct csp_buffer_pool_t {
+    inner: Arc<csp_buffer_pool_inner>,
+}
+
+impl csp_buffer_pool_t {
+    /// Allocate `count` buffers of `size` payload bytes up front
+    pub fn new(count: usize, size: usize) -> csp_buffer_pool_t {
+        let mut free = Vec::with_capacity(count);
+        for _ in 0..count {
+            free.push(vec![0u8; size].into_boxed_slice());
+        }
+        csp_buffer_pool_t {
+            inner: Arc::new(csp_buffer_pool_inner {
+                count,
+                size,
+                free: Mutex::new(free),
+                high_water: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Take a buffer with room for at least `size` payload bytes, like csp_buffer_get().
+    /// Fails if `size` is larger than the pool's buffers or none are free.
+    pub fn get(&self, size: usize) -> Result<csp_packet_t, CspError> {
+        if size > self.inner.size {
+            return Err(CspError::TooLarge { length: size, max: self.inner.size });
+        }
+        let (data, in_use) = {
+            let mut free = self.inner.free.lock().unwrap();
+            let data = free.pop().ok_or(CspError::NoBuffers)?;
+            (data, self.inner.count - free.len())
+        };
+        self.inner.high_water.fetch_max(in_use, Ordering::Relaxed);
+        Ok(csp_packet_t {
+            length: 0,
+            id: csp_id_t::default(),
+            data,
+            pool: Some(self.clone()),
+        })
+    }
+
+    /// Take a buffer holding a copy of `data`, like csp_buffer_clone()
+    pub fn get_slice(&self, id: csp_id_t, data: &[u8]) -> Result<csp_packet_t, CspError> {
+        let mut packet = self.get(data.len())?;
+        packet.id = id;
+        packet.append(data)?;
+        Ok(packet)
+    }
+
+    /// Take a buffer for a header followed by payload, like csp_packet_t::from_bytes()
+    pub fn get_bytes(&self, version: csp_version_t, raw: &[u8]) -> Result<csp_packet_t, CspError> {
+        let id = csp_id_t::decode(version, raw)?;
+        self.get_slice(id, &raw[version.header_size()..])
+    }
+
+    /// Number of free buffers, the value reported on CSP_BUF_FREE
+    pub fn remaining(&self) -> usize {
+        self.inner.free.lock().unwrap().len()
+    }
+
+    /// Most buffers ever in use at the same time
+    pub fn high_water(&self) -> usize {
+        self.inner.high_water.load(Ordering::Relaxed)
+    }
+
+    /// Total number of buffers in the pool
+    pub fn count(&self) -> usize {
+        self.inner.count
+    }
+
+    /// Payload bytes per buffer
+    pub fn size(&self) -> usize {
+        self.inner.size
+    }
+
+    // called from csp_packet_t's drop, like csp_buffer_free()
+    fn put(&self, data: Box<[u8]>) {
+        self.inner.free.lock().unwrap().push(data);
+    }
+}
+
+impl Drop for csp_packet_t {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.put(mem::take(&mut self.data));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CSP::csp_test::*;
+    use crate::CSP::csp_version_t::*;
+
+    #[test]
+    fn get() {
+        let pool = csp_buffer_pool_t::new(4, 32);
+        assert_eq!((pool.count(), pool.size(), pool.remaining(), pool.high_water()), (4, 32, 4, 0));
+
+        let mut packet = pool.get(10).unwrap();
+        assert_eq!(packet.length(), 0);
+        assert_eq!(packet.capacity(), 32);
+        packet.append(&payload(32)).unwrap();
+        assert!(packet.append(b"x").is_err());
+        assert_eq!(pool.remaining(), 3);
+
+        let copy = pool.get_slice(CSP_TEST_ID, b"hello").unwrap();
+        assert_eq!((copy.id, copy.data()), (CSP_TEST_ID, &b"hello"[..]));
+        let raw = packet_from(3, b"raw").to_bytes(CSP_VERSION_2).unwrap();
+        let decoded = pool.get_bytes(CSP_VERSION_2, &raw).unwrap();
+        assert_eq!((decoded.id.src, decoded.data()), (3, &b"raw"[..]));
+        assert_eq!(pool.remaining(), 1);
+    }
+
+    #[test]
+    fn high_water() {
+        let pool = csp_buffer_pool_t::new(4, 32);
+        let packets: Vec<csp_packet_t> = (0..3).map(|_| pool.get(0).unwrap()).collect();
+        assert_eq!(pool.high_water(), 3);
+        drop(packets);
+        let _one = pool.get(0).unwrap();
+        assert_eq!(pool.high_water(), 3);
+        let _more: Vec<csp_packet_t> = (0..3).map(|_| pool.get(0).unwrap()).collect();
+        assert_eq!(pool.high_water(), 4);
+    }
+
+    #[test]
+    fn returned_on_drop() {
+        let pool = csp_buffer_pool_t::new(2, 32);
+        let packet = pool.get(32).unwrap();
+        let clone = pool.clone().get(32).unwrap();
+        assert_eq!(pool.remaining(), 0);
+        drop(packet);
+        assert_eq!(pool.remaining(), 1);
+        drop(clone);
+        assert_eq!(pool.remaining(), 2);
+
+        // packets not from a pool go nowhere
+        drop(csp_packet_t::new(32));
+        assert_eq!(pool.remaining(), 2);
+
+        // a returned buffer is handed out again, emptied
+        let mut packet = pool.get(4).unwrap();
+        packet.append(b"used").unwrap();
+        drop(packet);
+        let packets = [pool.get(4).unwrap(), pool.get(4).unwrap()];
+        assert!(packets.iter().all(|p| p.length() == 0));
+    }
+
+    #[test]
+    fn too_large() {
+        let pool = csp_buffer_pool_t::new(2, 32);
+        assert!(matches!(pool.get(33), Err(CspError::TooLarge { length: 33, max: 32 })));
+        assert!(matches!(pool.get_slice(CSP_TEST_ID, &payload(33)), Err(CspError::TooLarge { .. })));
+        assert_eq!(pool.remaining(), 2);
+        assert_eq!(pool.high_water(), 0);
+    }
+
+    #[test]
+    fn no_buffers() {
+        let pool = csp_buffer_pool_t::new(2, 32);
+        let _packets = [pool.get(0).unwrap(), pool.get_slice(CSP_TEST_ID, b"x").unwrap()];
+        assert!(matches!(pool.get(0), Err(CspError::NoBuffers)));
+        assert!(matches!(pool.get_slice(CSP_TEST_ID, b"x"), Err(CspError::NoBuffers)));
+        assert_eq!(pool.remaining(), 0);
+        assert_eq!(pool.high_water(), 2);
+    }
+}
@@ -0,0 +1,254 @@
+// SocketCAN driver for the CAN interface
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/drivers/can/can_socketcan.c
+//
+// Frames are sent straight from the caller. A receive thread reads the socket
+// with a short timeout, feeds every frame to a csp_can_rx_t and queues the
+// packets it completes until receive() picks them up; the timeout also lets it
+// purge stale partial packets on a quiet bus. Works the same on a real
+// controller (can0) and a virtual one (ip link add dev vcan0 type vcan).
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use socketcan::{CANFilter, CANSocket};
+
+use super::*;
+
+// how long the receive thread blocks on the socket
+const CSP_CAN_POLL: Duration = Duration::from_millis(100);
+
+/// CAN interface settings
+#[derive(Debug, Clone)]
+pub struct csp_can_socketcan_conf_t {
+    pub name: String,            // !< Interface name
+    pub ifname: String,          // !< Network interface, e.g. can0 or vcan0
+    pub promisc: bool,           // !< Receive every CFP frame on the bus, not just ours and broadcast
+    pub write_timeout: Duration, // !< Give up on a frame the controller does not take within this time
+    pub rx_timeout: Duration,    // !< How long a partly received packet waits for its next frame
+}
+
+impl Default for csp_can_socketcan_conf_t {
+    fn default() -> csp_can_socketcan_conf_t {
+        csp_can_socketcan_conf_t {
+            name: String::from("CAN"),
+            ifname: String::from("can0"),
+            promisc: false,
+            write_timeout: Duration::from_secs(1),
+            rx_timeout: CFP_TIMEOUT,
+        }
+    }
+}
+
+struct csp_can_socketcan_shared {
+    socket: CANSocket,
+    running: AtomicBool,
+    stats: csp_iface_stats_t,
+}
+
+/// CAN interface on an open socket, with its receive thread.
+///
+/// Dropping it stops the receive thread and closes the socket.
+pub struct csp_can_socketcan_t {
+    conf: csp_can_socketcan_conf_t,
+    shared: Arc<csp_can_socketcan_shared>,
+    rx: Mutex<mpsc::Receiver<csp_packet_t>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+// accept extended frames whose CFP destination is `addr`
+fn csp_can_filter(addr: u16) -> Result<CANFilter, CspError> {
+    let id = socketcan::EFF_FLAG | ((addr as u32) << CFP_DST_MASK.trailing_zeros());
+    CANFilter::new(id, socketcan::EFF_FLAG | CFP_DST_MASK)
+        .map_err(|e| CspError::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string())))
+}
+
+impl csp_can_socketcan_t {
+    /// Open `conf.ifname`, install the receive filters for `node`'s address
+    /// and start the receive thread, which reassembles into `node`'s buffers
+    pub fn open(node: &csp_node_t, conf: csp_can_socketcan_conf_t) -> Result<csp_can_socketcan_t, CspError> {
+        // CFP carries 1.x addresses
+        let addr = node.address();
+        if addr > CSP_ID_HOST_MAX as u16 {
+            return Err(CspError::InvalidAddress(addr));
+        }
+        let socket = CANSocket::open(&conf.ifname)?;
+        // frames to other nodes are filtered out by the kernel
+        if !conf.promisc {
+            socket.set_filter(&[csp_can_filter(addr)?, csp_can_filter(CSP_ID_HOST_MAX as u16)?])?;
+        }
+        socket.set_read_timeout(CSP_CAN_POLL)?;
+        socket.set_write_timeout(conf.write_timeout)?;
+
+        let shared = Arc::new(csp_can_socketcan_shared {
+            socket,
+            running: AtomicBool::new(true),
+            stats: csp_iface_stats_t::default(),
+        });
+        let (tx, rx) = mpsc::channel();
+        let cfp = csp_can_rx_t::new(conf.rx_timeout, node.buffer_pool().clone());
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name(format!("csp_can {}", conf.ifname))
+                .spawn(move || csp_can_rx_task(shared, cfp, tx))?
+        };
+
+        Ok(csp_can_socketcan_t {
+            conf,
+            shared,
+            rx: Mutex::new(rx),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn conf(&self) -> &csp_can_socketcan_conf_t {
+        &self.conf
+    }
+
+    fn write_frames(&self, packet: &csp_packet_t, via: u16) -> Result<(), CspError> {
+        for frame in csp8u_CAN(packet, via)? {
+            self.shared.socket.write_frame(&frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl CspInterface for csp_can_socketcan_t {
+    fn name(&self) -> &str {
+        &self.conf.name
+    }
+
+    fn mtu(&self) -> usize {
+        CFP_MAX_LENGTH
+    }
+
+    /// Send `packet` as CFP frames addressed to `via`, or to the packet's
+    /// destination when `via` is CSP_NO_VIA_ADDRESS.
+    /// Fails with Timeout if the controller's queue stays full for `write_timeout`.
+    fn transmit(&self, packet: &csp_packet_t, via: u16) -> Result<(), CspError> {
+        match self.write_frames(packet, via) {
+            Ok(()) => {
+                self.shared.stats.count_tx(packet.length());
+                Ok(())
+            }
+            Err(e) => {
+                csp_iface_stats_t::add(&self.shared.stats.tx_error, 1);
+                Err(e)
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for the next reassembled packet.
+    /// Fails with Io(BrokenPipe) once the receive thread has stopped on a socket error.
+    fn receive(&self, timeout: Duration) -> Result<csp_packet_t, CspError> {
+        match self.rx.lock().unwrap().recv_timeout(timeout) {
+            Ok(packet) => Ok(packet),
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(CspError::Timeout),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(CspError::Io(std::io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn stats(&self) -> &csp_iface_stats_t {
+        &self.shared.stats
+    }
+}
+
+impl Drop for csp_can_socketcan_t {
+    fn drop(&mut self) {
+        self.shared.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// receive thread: read frames, feed reassembly, queue complete packets.
+// Stops when the interface is dropped or the socket fails (e.g. interface removed).
+fn csp_can_rx_task(shared: Arc<csp_can_socketcan_shared>, mut cfp: csp_can_rx_t, tx: mpsc::Sender<csp_packet_t>) {
+    let mut last = cfp.stats();
+    while shared.running.load(Ordering::SeqCst) {
+        match shared.socket.read_frame() {
+            Ok(frame) => {
+                if let Some(packet) = cfp.rx_frame(&frame) {
+                    shared.stats.count_rx(packet.length());
+                    if tx.send(packet).is_err() {
+                        csp_iface_stats_t::add(&shared.stats.drop, 1);
+                    }
+                }
+            }
+            Err(e) => match e.kind() {
+                std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted => {
+                    cfp.purge(Instant::now())
+                }
+                _ => break,
+            },
+        }
+        let now = cfp.stats();
+        csp_iface_stats_t::add(&shared.stats.frame, now.frame - last.frame);
+        csp_iface_stats_t::add(&shared.stats.rx_error, now.overrun - last.overrun);
+        csp_iface_stats_t::add(&shared.stats.drop, (now.drop - last.drop) + (now.timeout - last.timeout));
+        last = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CSP::csp_test::*;
+
+    const WAIT: Duration = Duration::from_secs(1);
+
+    fn node(address: u16) -> csp_node_t {
+        csp_node_t::new(csp_conf_t { address, ..Default::default() }).unwrap()
+    }
+
+    fn open(node: &csp_node_t, promisc: bool) -> csp_can_socketcan_t {
+        let conf = csp_can_socketcan_conf_t { ifname: String::from("vcan0"), promisc, ..Default::default() };
+        csp_can_socketcan_t::open(node, conf).unwrap()
+    }
+
+    fn packet_to(src: u16, dst: u16, data: &[u8]) -> csp_packet_t {
+        csp_packet_t::from_slice(csp_id_t { src, dst, ..CSP_TEST_ID }, data)
+    }
+
+    // needs a virtual bus: ip link add dev vcan0 type vcan && ip link set up vcan0
+    #[test]
+    #[ignore = "needs vcan0"]
+    fn vcan0() {
+        let (a, b) = (node(3), node(5));
+        let (can_a, can_b) = (open(&a, false), open(&b, false));
+
+        // long enough to take several frames each way
+        let request = packet_to(3, 5, &payload(100));
+        can_a.transmit(&request, CSP_NO_VIA_ADDRESS).unwrap();
+        let received = can_b.receive(WAIT).unwrap();
+        assert_eq!((received.id, received.data()), (request.id, request.data()));
+        let reply = packet_to(5, 3, &payload(CFP_MAX_LENGTH));
+        can_b.transmit(&reply, CSP_NO_VIA_ADDRESS).unwrap();
+        let received = can_a.receive(WAIT).unwrap();
+        assert_eq!((received.id, received.data()), (reply.id, reply.data()));
+
+        // the kernel filters out frames to other nodes unless promiscuous
+        let sniffer = open(&node(7), true);
+        let other = packet_to(3, 9, b"not for 5");
+        can_a.transmit(&other, CSP_NO_VIA_ADDRESS).unwrap();
+        assert_eq!(sniffer.receive(WAIT).unwrap().data(), other.data());
+        assert!(matches!(can_b.receive(Duration::from_millis(200)), Err(CspError::Timeout)));
+
+        // frames go to the via address, the packet keeps its destination
+        can_a.transmit(&other, 5).unwrap();
+        assert_eq!(can_b.receive(WAIT).unwrap().id.dst, 9);
+        assert_eq!(sniffer.receive(WAIT).unwrap().id.dst, 9);
+
+        // broadcast frames reach everyone
+        can_a.transmit(&packet_to(3, CSP_ID_HOST_MAX as u16, b"all"), CSP_NO_VIA_ADDRESS).unwrap();
+        assert_eq!(can_b.receive(WAIT).unwrap().data(), b"all");
+        assert_eq!(sniffer.receive(WAIT).unwrap().data(), b"all");
+
+        assert_eq!(can_a.stats().tx.load(Ordering::Relaxed), 4);
+        assert_eq!(can_b.stats().rx.load(Ordering::Relaxed), 3);
+    }
+}
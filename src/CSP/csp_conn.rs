@@ -0,0 +1,453 @@
+// Connections
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/csp_conn.c
+//
+// A connection is a pair of identifiers: idout for the packets we send and
+// idin, its mirror image, for the packets we expect back. The node keeps the
+// open connections in a table keyed on the CSP_ID_CONN_MASK bits of idin
+// (addresses and ports, not priority or flags), which the router uses to
+// hand each incoming packet to its connection's queue.
+// Connections opened with CSP_O_RDP also carry RDP state, see csp_rdp.rs.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use super::*;
+
+// the identifier bits that tell connections apart
+fn csp_conn_key(version: csp_version_t, id: &csp_id_t) -> Result<u64, CspError> {
+    match version {
+        csp_version_t::CSP_VERSION_1 => Ok((id.ext()? & CSP_ID_CONN_MASK) as u64),
+        csp_version_t::CSP_VERSION_2 => Ok(id.ext2()? & CSP_ID2_CONN_MASK),
+    }
+}
+
+pub(crate) struct csp_conn_inner {
+    pub(crate) idin: csp_id_t,
+    pub(crate) idout: csp_id_t,
+    pub(crate) opts: u32,
+    pub(crate) rdp: Option<Mutex<csp_rdp_t>>,
+    // wakes RDP senders waiting for the handshake or room in the window
+    pub(crate) tx_wait: Condvar,
+    rx_tx: Mutex<Option<mpsc::SyncSender<csp_packet_t>>>,
+    rx: Mutex<mpsc::Receiver<csp_packet_t>>,
+    queued: AtomicUsize,
+}
+
+impl csp_conn_inner {
+    /// Queue an incoming packet, handing it back if the queue is full or shut down
+    pub(crate) fn enqueue(&self, packet: csp_packet_t) -> Result<(), csp_packet_t> {
+        let rx_tx = self.rx_tx.lock().unwrap();
+        let rx_tx = match &*rx_tx {
+            Some(rx_tx) => rx_tx,
+            None => return Err(packet),
+        };
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        rx_tx.try_send(packet).map_err(|e| {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            match e {
+                mpsc::TrySendError::Full(packet) | mpsc::TrySendError::Disconnected(packet) => packet,
+            }
+        })
+    }
+
+    /// Packets waiting to be read
+    pub(crate) fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Take no more packets; reads fail with Reset once the queue is empty
+    pub(crate) fn shutdown(&self) {
+        self.rx_tx.lock().unwrap().take();
+    }
+
+    fn read(&self, timeout: Duration) -> Result<csp_packet_t, CspError> {
+        match self.rx.lock().unwrap().recv_timeout(timeout) {
+            Ok(packet) => {
+                self.queued.fetch_sub(1, Ordering::SeqCst);
+                Ok(packet)
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(CspError::Timeout),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(CspError::Reset),
+        }
+    }
+}
+
+/// The node's open connections, like libcsp's connection pool
+pub(crate) struct csp_conn_table_t {
+    version: csp_version_t,
+    conn_max: usize,
+    conn_queue_length: usize,
+    sport_min: u8,
+    sport: u8, // last ephemeral port handed out
+    rdp_opt: csp_rdp_opt_t,
+    conns: HashMap<u64, Arc<csp_conn_inner>>,
+}
+
+impl csp_conn_table_t {
+    pub(crate) fn new(conf: &csp_conf_t) -> csp_conn_table_t {
+        csp_conn_table_t {
+            version: conf.version,
+            conn_max: conf.conn_max,
+            conn_queue_length: conf.conn_queue_length,
+            sport_min: conf.port_max_bind + 1,
+            sport: CSP_ID_PORT_MAX as u8,
+            rdp_opt: conf.rdp_opt,
+            conns: HashMap::new(),
+        }
+    }
+
+    /// Connection an incoming packet belongs to, like csp_conn_find()
+    pub(crate) fn find(&self, id: &csp_id_t) -> Option<Arc<csp_conn_inner>> {
+        let key = csp_conn_key(self.version, id).ok()?;
+        self.conns.get(&key).cloned()
+    }
+
+    /// Open a connection, like csp_conn_new(). It runs RDP, with the node's
+    /// settings to start with, if `idout` has CSP_FRDP.
+    pub(crate) fn new_conn(&mut self, idin: csp_id_t, idout: csp_id_t, opts: u32) -> Result<Arc<csp_conn_inner>, CspError> {
+        let key = csp_conn_key(self.version, &idin)?;
+        if self.conns.len() >= self.conn_max || self.conns.contains_key(&key) {
+            return Err(CspError::NoConnections);
+        }
+        let (rx_tx, rx) = mpsc::sync_channel(self.conn_queue_length);
+        let rdp = if idout.flags as u32 & CSP_FRDP != 0 {
+            Some(Mutex::new(csp_rdp_t::new(self.rdp_opt)))
+        } else {
+            None
+        };
+        let conn = Arc::new(csp_conn_inner {
+            idin,
+            idout,
+            opts,
+            rdp,
+            tx_wait: Condvar::new(),
+            rx_tx: Mutex::new(Some(rx_tx)),
+            rx: Mutex::new(rx),
+            queued: AtomicUsize::new(0),
+        });
+        self.conns.insert(key, conn.clone());
+        Ok(conn)
+    }
+
+    /// Close a connection, like csp_close(). Packets still queued are dropped with it.
+    pub(crate) fn remove(&mut self, conn: &Arc<csp_conn_inner>) {
+        if let Ok(key) = csp_conn_key(self.version, &conn.idin) {
+            if self.conns.get(&key).is_some_and(|c| Arc::ptr_eq(c, conn)) {
+                self.conns.remove(&key);
+            }
+        }
+    }
+
+    /// Every open connection
+    pub(crate) fn list(&self) -> Vec<Arc<csp_conn_inner>> {
+        self.conns.values().cloned().collect()
+    }
+
+    // next ephemeral port not used by an open connection to the same node and port,
+    // cycling through port_max_bind + 1 ..= CSP_ID_PORT_MAX like csp_connect()
+    fn ephemeral_port(&mut self, mut idin: csp_id_t) -> Result<u8, CspError> {
+        let count = CSP_ID_PORT_MAX as u8 - self.sport_min + 1;
+        for _ in 0..count {
+            self.sport = if self.sport >= CSP_ID_PORT_MAX as u8 { self.sport_min } else { self.sport + 1 };
+            idin.dport = self.sport;
+            if self.find(&idin).is_none() {
+                return Ok(self.sport);
+            }
+        }
+        Err(CspError::NoConnections)
+    }
+}
+
+// option pairs (enable, disable) connect() resolves and the header flag they control
+const CSP_CONN_OPTS: [(u32, u32, u32); 4] = [
+    (CSP_O_RDP, CSP_O_NORDP, CSP_FRDP),
+    (CSP_O_HMAC, CSP_O_NOHMAC, CSP_FHMAC),
+    (CSP_O_XTEA, CSP_O_NOXTEA, CSP_FXTEA),
+    (CSP_O_CRC32, CSP_O_NOCRC32, CSP_FCRC32),
+];
+
+// merge connect() options with the node's defaults: a disable flag clears
+// both its enable flag and the default, so a pair never has both bits set.
+// Returns the connection's options and its header flags.
+fn csp_conn_opts(opts: u32, dfl_so: u32) -> (u32, u32) {
+    let mut merged = opts | dfl_so;
+    let mut flags = 0;
+    for &(enable, disable, flag) in CSP_CONN_OPTS.iter() {
+        if opts & disable != 0 {
+            merged &= !enable;
+            merged |= disable;
+        } else if merged & enable != 0 {
+            merged &= !disable;
+            flags |= flag;
+        }
+    }
+    (merged, flags)
+}
+
+/// An open connection, like libcsp's csp_conn_t. Dropping it closes it.
+pub struct csp_conn_t {
+    pub(crate) node: csp_node_t,
+    pub(crate) inner: Arc<csp_conn_inner>,
+}
+
+impl csp_node_t {
+    /// Open a connection to port `dport` on node `dst`, like csp_connect().
+    /// `opts` are CSP_O_* flags, on top of the node's conn_dfl_so; a CSP_O_NO*
+    /// flag turns off what the default turns on. The source port is a free one
+    /// above port_max_bind. With CSP_O_RDP, `timeout` bounds the handshake,
+    /// which needs the router running.
+    pub fn connect(&self, prio: csp_prio_t, dst: u16, dport: u8, timeout: Duration, opts: u32) -> Result<csp_conn_t, CspError> {
+        let (opts, flags) = csp_conn_opts(opts, self.conf().conn_dfl_so);
+
+        let mut idout = csp_id_t::new(self.version(), prio, self.address(), dst, dport, 0, flags as u8)?;
+        let mut idin = csp_id_t {
+            src: idout.dst,
+            dst: idout.src,
+            dport: idout.sport,
+            sport: idout.dport,
+            ..idout
+        };
+
+        let inner = {
+            let mut conns = self.inner.conns.lock().unwrap();
+            let sport = conns.ephemeral_port(idin)?;
+            idout.sport = sport;
+            idin.dport = sport;
+            conns.new_conn(idin, idout, opts)?
+        };
+        let conn = csp_conn_t { node: self.clone(), inner };
+        if conn.inner.rdp.is_some() {
+            csp_rdp_connect(self, &conn.inner, timeout)?;
+        }
+        Ok(conn)
+    }
+
+    // hand a packet for this node to its connection, dropping it if it fails
+    // the connection's security options or its queue is full. Packets without
+    // a connection go to the socket bound to their port, see csp_port.rs.
+    pub(crate) fn deliver(&self, mut packet: csp_packet_t, iface: Arc<dyn CspInterface>) {
+        let conn = self.inner.conns.lock().unwrap().find(&packet.id);
+        let conn = match conn {
+            Some(conn) => conn,
+            None => return self.deliver_new(packet, iface),
+        };
+        if csp_route_security_check(&self.inner.keys, conn.opts, &mut packet).is_err() {
+            return csp_iface_stats_t::add(&iface.stats().autherr, 1);
+        }
+        let rdp = packet.id.flags as u32 & CSP_FRDP != 0;
+        match &conn.rdp {
+            Some(_) if rdp => csp_rdp_new_packet(self, &conn, packet),
+            None if !rdp => {
+                if conn.enqueue(packet).is_err() {
+                    csp_iface_stats_t::add(&iface.stats().drop, 1);
+                }
+            }
+            _ => csp_iface_stats_t::add(&iface.stats().drop, 1),
+        }
+    }
+
+    // give a packet the connection's header, then the trailers its options call for
+    pub(crate) fn conn_secure(&self, conn: &csp_conn_inner, packet: &mut csp_packet_t) -> Result<(), CspError> {
+        packet.id = conn.idout;
+        csp_send_security(&self.inner.keys, conn.opts, packet)
+    }
+
+    // send on a connection
+    pub(crate) fn conn_transmit(&self, conn: &csp_conn_inner, mut packet: csp_packet_t) -> Result<(), CspError> {
+        self.conn_secure(conn, &mut packet)?;
+        self.send_direct(&packet)
+    }
+
+    /// Resend, ack and time out RDP connections, like csp_conn_check_timeouts()
+    pub fn conn_check_timeouts(&self) {
+        let conns = self.inner.conns.lock().unwrap().list();
+        for conn in conns.iter().filter(|c| c.rdp.is_some()) {
+            csp_rdp_check_timeouts(self, conn);
+        }
+    }
+}
+
+impl csp_conn_t {
+    /// Send `packet` on the connection, like csp_send(). The header is set from
+    /// the connection and the trailers its options call for are added, so the
+    /// packet needs room for them, and for the RDP header on RDP connections.
+    /// Those wait up to conn_timeout for room in the window and fail with
+    /// Reset once the peer has closed.
+    pub fn send(&self, packet: csp_packet_t) -> Result<(), CspError> {
+        if self.inner.rdp.is_some() {
+            return csp_rdp_send(&self.node, &self.inner, packet);
+        }
+        self.node.conn_transmit(&self.inner, packet)
+    }
+
+    /// Next packet on the connection, waiting up to `timeout`, like csp_read().
+    /// On RDP connections packets come in order, and Reset follows the last
+    /// one once the peer has closed.
+    pub fn read(&self, timeout: Duration) -> Result<csp_packet_t, CspError> {
+        let packet = self.inner.read(timeout)?;
+        csp_rdp_read(&self.node, &self.inner);
+        Ok(packet)
+    }
+
+    /// Close the connection, like csp_close(). Same as dropping it.
+    /// An open RDP connection sends RST and stays in the table until the
+    /// peer answers or conn_timeout passes.
+    pub fn close(self) {}
+
+    /// Identifier of outgoing packets
+    pub fn idout(&self) -> csp_id_t {
+        self.inner.idout
+    }
+
+    /// Identifier incoming packets are matched against
+    pub fn idin(&self) -> csp_id_t {
+        self.inner.idin
+    }
+
+    pub fn opts(&self) -> u32 {
+        self.inner.opts
+    }
+
+    /// RDP state, `None` if the connection does not run RDP
+    pub fn rdp_state(&self) -> Option<csp_rdp_state_t> {
+        self.inner.rdp.as_ref().map(|rdp| rdp.lock().unwrap().state())
+    }
+
+    /// Destination port, like csp_conn_dport()
+    pub fn dport(&self) -> u8 {
+        self.inner.idout.dport
+    }
+
+    /// Source port, like csp_conn_sport()
+    pub fn sport(&self) -> u8 {
+        self.inner.idout.sport
+    }
+
+    /// Destination address, like csp_conn_dst()
+    pub fn dst(&self) -> u16 {
+        self.inner.idout.dst
+    }
+
+    /// Source address, like csp_conn_src()
+    pub fn src(&self) -> u16 {
+        self.inner.idout.src
+    }
+}
+
+impl Drop for csp_conn_t {
+    fn drop(&mut self) {
+        if csp_rdp_close(&self.node, &self.inner) {
+            self.node.inner.conns.lock().unwrap().remove(&self.inner);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CSP::csp_prio_t::*;
+
+    const WAIT: Duration = Duration::from_secs(1);
+
+    fn node(conf: csp_conf_t) -> csp_node_t {
+        let node = csp_node_t::new(csp_conf_t { address: 4, ..conf }).unwrap();
+        node.route_start_task().unwrap();
+        node
+    }
+
+    #[test]
+    fn loopback() {
+        let node = node(csp_conf_t::default());
+        let socket = node.socket(CSP_SO_NONE).unwrap();
+        socket.bind(10).unwrap();
+        socket.listen(5).unwrap();
+
+        let client = node.connect(CSP_PRIO_HIGH, 4, 10, Duration::ZERO, CSP_O_NONE).unwrap();
+        assert_eq!((client.src(), client.dst(), client.dport()), (4, 4, 10));
+        assert_eq!(client.idin().dport, client.sport());
+        client.send(csp_packet_t::from_slice(csp_id_t::default(), b"request")).unwrap();
+
+        let server = socket.accept(WAIT).unwrap();
+        assert_eq!(server.idin(), client.idout());
+        assert_eq!(server.idout(), client.idin());
+        assert_eq!(server.read(WAIT).unwrap().data(), b"request");
+        server.send(csp_packet_t::from_slice(csp_id_t::default(), b"reply")).unwrap();
+        let reply = client.read(WAIT).unwrap();
+        assert_eq!(reply.data(), b"reply");
+        assert_eq!(reply.id.pri, CSP_PRIO_HIGH);
+        assert!(matches!(client.read(Duration::from_millis(100)), Err(CspError::Timeout)));
+
+        assert_eq!(node.inner.conns.lock().unwrap().list().len(), 2);
+        client.close();
+        server.close();
+        assert!(node.inner.conns.lock().unwrap().list().is_empty());
+        node.stop();
+    }
+
+    #[test]
+    fn ephemeral_ports() {
+        let node = node(csp_conf_t { port_max_bind: 40, conn_max: 100, ..Default::default() });
+        let sport_min = node.conf().port_max_bind + 1;
+        let count = (sport_min..=CSP_ID_PORT_MAX as u8).count();
+
+        let connect = || node.connect(CSP_PRIO_NORM, 5, 10, Duration::ZERO, CSP_O_NONE);
+        let mut conns: Vec<csp_conn_t> = (0..count).map(|_| connect().unwrap()).collect();
+        let mut sports: Vec<u8> = conns.iter().map(|c| c.sport()).collect();
+        sports.sort();
+        sports.dedup();
+        assert_eq!(sports, (sport_min..=CSP_ID_PORT_MAX as u8).collect::<Vec<u8>>());
+
+        // every port is taken for node 5 port 10, not for other destinations
+        assert!(matches!(connect(), Err(CspError::NoConnections)));
+        let other = node.connect(CSP_PRIO_NORM, 5, 11, Duration::ZERO, CSP_O_NONE).unwrap();
+        assert!((sport_min..=CSP_ID_PORT_MAX as u8).contains(&other.sport()));
+
+        // closing a connection frees its port
+        let freed = conns.swap_remove(7).sport();
+        let again = connect().unwrap();
+        assert_eq!(again.sport(), freed);
+        node.stop();
+    }
+
+    #[test]
+    fn conn_max() {
+        let node = node(csp_conf_t { conn_max: 3, ..Default::default() });
+        let connect = || node.connect(CSP_PRIO_NORM, 5, 10, Duration::ZERO, CSP_O_NONE);
+        let mut conns: Vec<csp_conn_t> = (0..3).map(|_| connect().unwrap()).collect();
+        assert!(matches!(node.connect(CSP_PRIO_NORM, 6, 20, Duration::ZERO, CSP_O_NONE), Err(CspError::NoConnections)));
+        conns.pop().unwrap().close();
+        conns.push(node.connect(CSP_PRIO_NORM, 6, 20, Duration::ZERO, CSP_O_NONE).unwrap());
+
+        // a full table also refuses connections from other nodes
+        let socket = node.socket(CSP_SO_NONE).unwrap();
+        socket.bind(10).unwrap();
+        socket.listen(5).unwrap();
+        let mut packet = csp_packet_t::from_slice(csp_id_t::default(), b"request");
+        packet.id = csp_id_t::new(node.version(), CSP_PRIO_NORM, 7, 4, 10, 30, 0).unwrap();
+        node.send_direct(&packet).unwrap();
+        assert!(matches!(socket.accept(Duration::from_millis(100)), Err(CspError::Timeout)));
+        node.stop();
+    }
+
+    #[test]
+    fn disable_overrides_default() {
+        let (opts, flags) = csp_conn_opts(CSP_O_NOCRC32, CSP_O_CRC32 | CSP_O_RDP);
+        assert_eq!(opts, CSP_O_NOCRC32 | CSP_O_RDP);
+        assert_eq!(flags, CSP_FRDP);
+
+        let (opts, flags) = csp_conn_opts(CSP_O_NORDP, CSP_O_RDP | CSP_O_HMAC);
+        assert_eq!(opts, CSP_O_NORDP | CSP_O_HMAC);
+        assert_eq!(flags, CSP_FHMAC);
+    }
+
+    #[test]
+    fn enable_overrides_default_disable() {
+        let (opts, flags) = csp_conn_opts(CSP_O_XTEA, CSP_O_NOXTEA);
+        assert_eq!(opts, CSP_O_XTEA);
+        assert_eq!(flags, CSP_FXTEA);
+        assert_eq!(csp_conn_opts(CSP_O_NONE, CSP_O_NONE), (CSP_O_NONE, 0));
+    }
+}
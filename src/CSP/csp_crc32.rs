@@ -0,0 +1,100 @@
+// CRC32 (Castagnoli), as used by libcsp for CSP_FCRC32
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/csp_crc32.c
+//
+// The checksum covers the payload only (not the header, for compatibility with
+// csp 1.x) and is appended to it in network byte order.
+
+use super::*;
+
+/// Bytes added to the payload by csp_crc32_append()
+pub const CSP_CRC32_LENGTH: usize = 4;
+
+const CSP_CRC32_POLY: u32 = 0x82F6_3B78; // reversed 0x1EDC6F41
+
+const fn csp_crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ CSP_CRC32_POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CSP_CRC32_TAB: [u32; 256] = csp_crc32_table();
+
+/// CRC32-C of `data`
+pub fn csp_crc32_memory(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc = CSP_CRC32_TAB[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc ^ 0xFFFF_FFFF
+}
+
+/// Append the CRC32 of the payload to the payload
+pub fn csp_crc32_append(packet: &mut csp_packet_t) -> Result<(), CspError> {
+    let crc = csp_crc32_memory(packet.data());
+    packet.append(&crc.to_be_bytes())
+}
+
+/// Check the CRC32 at the end of the payload and strip it
+pub fn csp_crc32_verify(packet: &mut csp_packet_t) -> Result<(), CspError> {
+    let length = packet.length().checked_sub(CSP_CRC32_LENGTH).ok_or(CspError::CrcMismatch)?;
+    let crc = packet.read_u32(length).ok_or(CspError::CrcMismatch)?;
+    if crc != csp_crc32_memory(&packet.data()[..length]) {
+        return Err(CspError::CrcMismatch);
+    }
+    packet.set_length(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CSP::csp_test::*;
+
+    #[test]
+    fn known_answer() {
+        assert_eq!(csp_crc32_memory(b"123456789"), 0xE306_9283);
+        assert_eq!(csp_crc32_memory(b""), 0);
+    }
+
+    #[test]
+    fn append_verify() {
+        let mut p = packet(b"123456789");
+        csp_crc32_append(&mut p).unwrap();
+        assert_eq!(p.length(), 9 + CSP_CRC32_LENGTH);
+        assert_eq!(p.read_u32(9), Some(0xE306_9283));
+        csp_crc32_verify(&mut p).unwrap();
+        assert_eq!(p.data(), b"123456789");
+
+        csp_crc32_append(&mut p).unwrap();
+        p.write_u8(0, b'0').unwrap();
+        assert!(matches!(csp_crc32_verify(&mut p), Err(CspError::CrcMismatch)));
+        assert!(matches!(csp_crc32_verify(&mut packet(b"abc")), Err(CspError::CrcMismatch)));
+    }
+
+    #[test]
+    fn require_prohibit() {
+        let keys = csp_security_keys_t::default();
+        let mut p = packet(b"hello");
+        csp_send_security(&keys, CSP_O_CRC32, &mut p).unwrap();
+        assert_eq!(p.id.flags as u32, CSP_FCRC32);
+
+        let mut copy = csp_packet_t::from_slice(p.id, p.data());
+        assert!(matches!(csp_route_security_check(&keys, CSP_SO_CRC32PROHIB, &mut copy), Err(CspError::Prohibited(CSP_FCRC32))));
+        csp_route_security_check(&keys, CSP_SO_CRC32REQ, &mut p).unwrap();
+        assert_eq!(p.data(), b"hello");
+
+        let mut plain = packet(b"hello");
+        assert!(matches!(csp_route_security_check(&keys, CSP_SO_CRC32REQ, &mut plain), Err(CspError::Required(CSP_FCRC32))));
+        csp_route_security_check(&keys, CSP_SO_NONE, &mut plain).unwrap();
+        assert_eq!(plain.data(), b"hello");
+    }
+}
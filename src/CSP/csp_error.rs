@@ -0,0 +1,98 @@
+// Errors returned by the CSP stack
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/include/csp/csp_error.h
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum CspError {
+    Io(io::Error),                                  // !< I/O failure on an interface (serial, socketcan, sockets, zmq)
+    InvalidHeader,                                  // !< Header missing, truncated or with a field out of range
+    InvalidAddress(u16),                            // !< Address does not fit in the header
+    InvalidPort(u8),                                // !< Port above CSP_ID_PORT_MAX, or above port_max_bind for bind()
+    InvalidPriority(u8),                            // !< Priority above CSP_ID_PRIO_MAX
+    CrcMismatch,                                    // !< CRC32 missing or wrong
+    Prohibited(u32),                                // !< Packet uses a feature (CSP_F*) the socket prohibits
+    Required(u32),                                  // !< Packet lacks a feature (CSP_F*) the socket requires
+    HmacMismatch,                                   // !< HMAC missing or wrong
+    DecryptFailed,                                  // !< XTEA missing, no key or no nonce
+    Timeout,                                        // !< Operation timed out
+    NoRoute(u16),                                   // !< No route to address
+    InvalidNetmask(u8),                             // !< Netmask longer than an address
+    InvalidRoute { entry: String, reason: String }, // !< Route string entry that cannot be loaded
+    NoBuffers,                                      // !< Buffer pool exhausted
+    NoConnections,                                  // !< Connection table full or no free port
+    Reset,                                          // !< Connection closed by the peer
+    PortInUse(u8),                                  // !< Port already bound to a socket
+    NotSupported(u32),                              // !< Option (CSP_O_*/CSP_SO_*) not available here
+    TooLarge { length: usize, max: usize },         // !< Payload does not fit
+}
+
+impl fmt::Display for CspError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CspError::Io(e) => write!(f, "I/O error: {}", e),
+            CspError::InvalidHeader => write!(f, "invalid CSP header"),
+            CspError::InvalidAddress(addr) => write!(f, "invalid address {}", addr),
+            CspError::InvalidPort(port) => write!(f, "invalid port {}", port),
+            CspError::InvalidPriority(prio) => write!(f, "invalid priority {}", prio),
+            CspError::CrcMismatch => write!(f, "CRC32 mismatch"),
+            CspError::Prohibited(flag) => write!(f, "flag {:#04x} prohibited by socket options", flag),
+            CspError::Required(flag) => write!(f, "flag {:#04x} required by socket options", flag),
+            CspError::HmacMismatch => write!(f, "HMAC mismatch"),
+            CspError::DecryptFailed => write!(f, "XTEA decryption failed"),
+            CspError::Timeout => write!(f, "timed out"),
+            CspError::NoRoute(addr) => write!(f, "no route to address {}", addr),
+            CspError::InvalidNetmask(bits) => write!(f, "invalid netmask /{}", bits),
+            CspError::InvalidRoute { entry, reason } => write!(f, "invalid route \"{}\": {}", entry, reason),
+            CspError::NoBuffers => write!(f, "no free buffers"),
+            CspError::NoConnections => write!(f, "no free connections"),
+            CspError::Reset => write!(f, "connection reset by peer"),
+            CspError::PortInUse(port) => write!(f, "port {} already in use", port),
+            CspError::NotSupported(opts) => write!(f, "options {:#06x} not supported", opts),
+            CspError::TooLarge { length, max } => write!(f, "{} bytes exceeds the maximum of {}", length, max),
+        }
+    }
+}
+
+impl Error for CspError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CspError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// read/write timeouts surface as Timeout so callers can tell a quiet link from a broken one
+impl From<io::Error> for CspError {
+    fn from(e: io::Error) -> CspError {
+        match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => CspError::Timeout,
+            _ => CspError::Io(e),
+        }
+    }
+}
+
+impl From<serial::Error> for CspError {
+    fn from(e: serial::Error) -> CspError {
+        CspError::from(io::Error::from(e))
+    }
+}
+
+#[cfg(feature = "zmq")]
+impl From<zmq::Error> for CspError {
+    fn from(e: zmq::Error) -> CspError {
+        CspError::from(io::Error::from(e))
+    }
+}
+
+impl From<socketcan::CANSocketOpenError> for CspError {
+    fn from(e: socketcan::CANSocketOpenError) -> CspError {
+        match e {
+            socketcan::CANSocketOpenError::IOError(e) => CspError::from(e),
+            e => CspError::Io(io::Error::new(io::ErrorKind::NotFound, e.to_string())),
+        }
+    }
+}
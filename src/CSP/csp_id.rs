@@ -0,0 +1,316 @@
+// CSP identifier/header codec
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/include/csp/csp_types.h
+// https://github.com/libcsp/libcsp/blob/develop/src/csp_id.c
+//
+// CSP 1.x header, 32 bits, sent big endian:
+//
+//  31  30 29     25 24     20 19      14 13       8 7        0
+// +------+---------+---------+----------+----------+----------+
+// | pri  |   src   |   dst   |  dport   |  sport   |  flags   |
+// +------+---------+---------+----------+----------+----------+
+//
+// CSP 2.x header, 48 bits, sent big endian:
+//
+//  47  46 45       32 31       18 17      12 11       6 5        0
+// +------+-----------+-----------+----------+----------+----------+
+// | pri  |    dst    |    src    |  dport   |  sport   |  flags   |
+// +------+-----------+-----------+----------+----------+----------+
+
+use std::convert::TryFrom;
+
+use super::*;
+
+/// Size of the packed CSP 1.x header in bytes
+pub const CSP_HEADER_LENGTH: usize = 4;
+/// Size of the packed CSP 2.x header in bytes
+pub const CSP_ID2_HEADER_SIZE: usize = 6;
+
+/// Header version spoken by a node or an interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum csp_version_t {
+    #[default]
+    CSP_VERSION_1 = 1, // !< libcsp 1.x, 5 bit hosts, 32 bit header
+    CSP_VERSION_2 = 2, // !< libcsp 2.x, 14 bit hosts, 48 bit header
+}
+
+impl csp_version_t {
+    /// Bytes the header takes on the wire
+    pub fn header_size(self) -> usize {
+        match self {
+            csp_version_t::CSP_VERSION_1 => CSP_HEADER_LENGTH,
+            csp_version_t::CSP_VERSION_2 => CSP_ID2_HEADER_SIZE,
+        }
+    }
+
+    /// Bits per address in the header
+    pub fn host_bits(self) -> u8 {
+        match self {
+            csp_version_t::CSP_VERSION_1 => CSP_ID_HOST_SIZE,
+            csp_version_t::CSP_VERSION_2 => CSP_ID2_HOST_SIZE,
+        }
+    }
+
+    /// Highest address that fits in the header
+    pub fn host_max(self) -> u16 {
+        match self {
+            csp_version_t::CSP_VERSION_1 => CSP_ID_HOST_MAX as u16,
+            csp_version_t::CSP_VERSION_2 => CSP_ID2_HOST_MAX as u16,
+        }
+    }
+}
+
+/// CSP identifier, unpacked.
+///
+/// Replaces the old `union csp_id_t`, whose fields all aliased the same byte.
+/// Fields are plain values wide enough for either header version; `ext()`/`ext2()`
+/// pack them into the 1.x/2.x identifier and `from_ext()`/`from_ext2()` unpack one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct csp_id_t {
+    pub pri: csp_prio_t, // !< Priority
+    pub src: u16,        // !< Source address
+    pub dst: u16,        // !< Destination address
+    pub dport: u8,       // !< Destination port
+    pub sport: u8,       // !< Source port
+    pub flags: u8,       // !< Flags, see CSP_F*
+}
+
+// extract a field from a packed identifier using its mask
+fn csp_id_field(ext: u64, mask: u64) -> u64 {
+    (ext & mask) >> mask.trailing_zeros()
+}
+
+// place a field into a packed identifier using its mask
+fn csp_id_place(value: u64, mask: u64) -> u64 {
+    (value << mask.trailing_zeros()) & mask
+}
+
+// the priority field is 2 bits in both versions, so every value is a csp_prio_t
+fn csp_id_prio(ext: u64, mask: u64) -> csp_prio_t {
+    csp_prio_t::try_from(csp_id_field(ext, mask) as u8).unwrap_or_default()
+}
+
+impl csp_id_t {
+    /// Build an identifier, failing if any field is out of range for `version`.
+    pub fn new(version: csp_version_t, pri: csp_prio_t, src: u16, dst: u16, dport: u8, sport: u8, flags: u8) -> Result<csp_id_t, CspError> {
+        let id = csp_id_t { pri, src, dst, dport, sport, flags };
+        id.check(version)?;
+        Ok(id)
+    }
+
+    /// Check addresses, ports and flags against the CSP_ID_*_MAX (1.x) or
+    /// CSP_ID2_*_MAX (2.x) limits. The priority is always in range.
+    pub fn check(&self, version: csp_version_t) -> Result<(), CspError> {
+        let (port_max, flags_max) = match version {
+            csp_version_t::CSP_VERSION_1 => (CSP_ID_PORT_MAX as u64, CSP_ID_FLAGS_MAX as u64),
+            csp_version_t::CSP_VERSION_2 => (CSP_ID2_PORT_MAX, CSP_ID2_FLAGS_MAX),
+        };
+        for &addr in &[self.src, self.dst] {
+            if addr > version.host_max() {
+                return Err(CspError::InvalidAddress(addr));
+            }
+        }
+        for &port in &[self.dport, self.sport] {
+            if port as u64 > port_max {
+                return Err(CspError::InvalidPort(port));
+            }
+        }
+        if self.flags as u64 > flags_max {
+            return Err(CspError::InvalidHeader);
+        }
+        Ok(())
+    }
+
+    pub fn is_valid(&self, version: csp_version_t) -> bool {
+        self.check(version).is_ok()
+    }
+
+    /// Pack into the 32 bit CSP 1.x identifier (host order).
+    /// Fails if a field does not fit in its bits.
+    pub fn ext(&self) -> Result<u32, CspError> {
+        self.check(csp_version_t::CSP_VERSION_1)?;
+        let ext = csp_id_place(self.pri as u64, CSP_ID_PRIO_MASK as u64)
+            | csp_id_place(self.src as u64, CSP_ID_SRC_MASK as u64)
+            | csp_id_place(self.dst as u64, CSP_ID_DST_MASK as u64)
+            | csp_id_place(self.dport as u64, CSP_ID_DPORT_MASK as u64)
+            | csp_id_place(self.sport as u64, CSP_ID_SPORT_MASK as u64)
+            | csp_id_place(self.flags as u64, CSP_ID_FLAGS_MASK as u64);
+        Ok(ext as u32)
+    }
+
+    /// Unpack a 32 bit CSP 1.x identifier (host order). Every bit pattern is a valid header.
+    pub fn from_ext(ext: u32) -> csp_id_t {
+        let ext = ext as u64;
+        csp_id_t {
+            pri: csp_id_prio(ext, CSP_ID_PRIO_MASK as u64),
+            src: csp_id_field(ext, CSP_ID_SRC_MASK as u64) as u16,
+            dst: csp_id_field(ext, CSP_ID_DST_MASK as u64) as u16,
+            dport: csp_id_field(ext, CSP_ID_DPORT_MASK as u64) as u8,
+            sport: csp_id_field(ext, CSP_ID_SPORT_MASK as u64) as u8,
+            flags: csp_id_field(ext, CSP_ID_FLAGS_MASK as u64) as u8,
+        }
+    }
+
+    /// Pack into the 48 bit CSP 2.x identifier (host order, upper 16 bits zero).
+    /// Fails if a field does not fit in its bits.
+    pub fn ext2(&self) -> Result<u64, CspError> {
+        self.check(csp_version_t::CSP_VERSION_2)?;
+        Ok(csp_id_place(self.pri as u64, CSP_ID2_PRIO_MASK)
+            | csp_id_place(self.dst as u64, CSP_ID2_DST_MASK)
+            | csp_id_place(self.src as u64, CSP_ID2_SRC_MASK)
+            | csp_id_place(self.dport as u64, CSP_ID2_DPORT_MASK)
+            | csp_id_place(self.sport as u64, CSP_ID2_SPORT_MASK)
+            | csp_id_place(self.flags as u64, CSP_ID2_FLAGS_MASK))
+    }
+
+    /// Unpack a 48 bit CSP 2.x identifier (host order). Bits above 47 are ignored.
+    pub fn from_ext2(ext: u64) -> csp_id_t {
+        csp_id_t {
+            pri: csp_id_prio(ext, CSP_ID2_PRIO_MASK),
+            dst: csp_id_field(ext, CSP_ID2_DST_MASK) as u16,
+            src: csp_id_field(ext, CSP_ID2_SRC_MASK) as u16,
+            dport: csp_id_field(ext, CSP_ID2_DPORT_MASK) as u8,
+            sport: csp_id_field(ext, CSP_ID2_SPORT_MASK) as u8,
+            flags: csp_id_field(ext, CSP_ID2_FLAGS_MASK) as u8,
+        }
+    }
+
+    /// CSP 1.x header as it goes on the wire (big endian).
+    pub fn to_be_bytes(&self) -> Result<[u8; CSP_HEADER_LENGTH], CspError> {
+        self.ext().map(u32::to_be_bytes)
+    }
+
+    /// Read a CSP 1.x header off the wire (big endian).
+    /// Fails if fewer than CSP_HEADER_LENGTH bytes are given.
+    pub fn from_be_bytes(bytes: &[u8]) -> Result<csp_id_t, CspError> {
+        csp_id_t::decode(csp_version_t::CSP_VERSION_1, bytes)
+    }
+
+    /// Write the header for `version` to the start of `buf` (big endian).
+    /// Returns the number of bytes written. Fails if a field is out of range
+    /// or `buf` is too short.
+    pub fn encode(&self, version: csp_version_t, buf: &mut [u8]) -> Result<usize, CspError> {
+        let size = version.header_size();
+        if buf.len() < size {
+            return Err(CspError::TooLarge { length: size, max: buf.len() });
+        }
+        let ext = match version {
+            csp_version_t::CSP_VERSION_1 => self.ext()? as u64,
+            csp_version_t::CSP_VERSION_2 => self.ext2()?,
+        };
+        buf[..size].copy_from_slice(&ext.to_be_bytes()[8 - size..]);
+        Ok(size)
+    }
+
+    /// Read a `version` header from the start of `buf` (big endian).
+    /// Fails with InvalidHeader if `buf` is shorter than the header.
+    pub fn decode(version: csp_version_t, buf: &[u8]) -> Result<csp_id_t, CspError> {
+        let size = version.header_size();
+        if buf.len() < size {
+            return Err(CspError::InvalidHeader);
+        }
+        let mut ext = [0u8; 8];
+        ext[8 - size..].copy_from_slice(&buf[..size]);
+        let ext = u64::from_be_bytes(ext);
+        Ok(match version {
+            csp_version_t::CSP_VERSION_1 => csp_id_t::from_ext(ext as u32),
+            csp_version_t::CSP_VERSION_2 => csp_id_t::from_ext2(ext),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CSP::csp_prio_t::*;
+    use crate::CSP::csp_version_t::*;
+
+    fn id(pri: csp_prio_t, src: u16, dst: u16, dport: u8, sport: u8, flags: u8) -> csp_id_t {
+        csp_id_t { pri, src, dst, dport, sport, flags }
+    }
+
+    #[test]
+    fn ext() {
+        // pri 1 | src 10 | dst 20 | dport 30 | sport 40 | flags 0x05
+        let packed = (1 << 30) | (10 << 25) | (20 << 20) | (30 << 14) | (40 << 8) | 0x05;
+        assert_eq!(packed, 0x5547_A805);
+        let header = id(CSP_PRIO_HIGH, 10, 20, 30, 40, 0x05);
+        assert_eq!(header.ext().unwrap(), packed);
+        assert_eq!(csp_id_t::from_ext(packed), header);
+        assert_eq!(header.to_be_bytes().unwrap(), [0x55, 0x47, 0xA8, 0x05]);
+
+        let max = id(CSP_PRIO_LOW, 31, 31, 63, 63, 0xFF);
+        assert_eq!(max.ext().unwrap(), 0xFFFF_FFFF);
+        assert_eq!(csp_id_t::default().ext().unwrap(), 2 << 30);
+    }
+
+    #[test]
+    fn round_trip() {
+        for &header in &[
+            id(CSP_PRIO_CRITICAL, 0, 0, 0, 0, 0),
+            id(CSP_PRIO_NORM, 1, 31, 10, 63, CSP_FCRC32 as u8),
+            id(CSP_PRIO_LOW, 31, 0, 63, 0, 0xFF),
+        ] {
+            let mut buf = [0u8; CSP_HEADER_LENGTH];
+            assert_eq!(header.encode(CSP_VERSION_1, &mut buf).unwrap(), CSP_HEADER_LENGTH);
+            assert_eq!(csp_id_t::decode(CSP_VERSION_1, &buf).unwrap(), header);
+            assert_eq!(csp_id_t::from_be_bytes(&header.to_be_bytes().unwrap()).unwrap(), header);
+        }
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert!(matches!(id(CSP_PRIO_NORM, 32, 1, 0, 0, 0).ext(), Err(CspError::InvalidAddress(32))));
+        assert!(matches!(id(CSP_PRIO_NORM, 1, 32, 0, 0, 0).ext(), Err(CspError::InvalidAddress(32))));
+        assert!(matches!(id(CSP_PRIO_NORM, 1, 2, 64, 0, 0).ext(), Err(CspError::InvalidPort(64))));
+        assert!(matches!(csp_id_t::new(CSP_VERSION_1, CSP_PRIO_NORM, 1, 2, 0, 64, 0), Err(CspError::InvalidPort(64))));
+        let mut buf = [0u8; CSP_HEADER_LENGTH];
+        assert!(id(CSP_PRIO_NORM, 32, 1, 0, 0, 0).encode(CSP_VERSION_1, &mut buf).is_err());
+        assert!(matches!(csp_id_t::decode(CSP_VERSION_1, &buf[..3]), Err(CspError::InvalidHeader)));
+    }
+
+    #[test]
+    fn ext2() {
+        // pri 1 | dst 0x1234 | src 0x2abc | dport 30 | sport 40 | flags 0x05
+        let packed = (1 << 46) | (0x1234 << 32) | (0x2abc << 18) | (30 << 12) | (40 << 6) | 0x05;
+        assert_eq!(packed, 0x5234_AAF1_EA05);
+        let header = id(CSP_PRIO_HIGH, 0x2abc, 0x1234, 30, 40, 0x05);
+        assert_eq!(header.ext2().unwrap(), packed);
+        assert_eq!(csp_id_t::from_ext2(packed), header);
+        let mut buf = [0u8; CSP_ID2_HEADER_SIZE];
+        assert_eq!(header.encode(CSP_VERSION_2, &mut buf).unwrap(), CSP_ID2_HEADER_SIZE);
+        assert_eq!(buf, [0x52, 0x34, 0xAA, 0xF1, 0xEA, 0x05]);
+
+        let max = id(CSP_PRIO_LOW, 0x3FFF, 0x3FFF, 63, 63, 0x3F);
+        assert_eq!(max.ext2().unwrap(), 0xFFFF_FFFF_FFFF);
+        // bits above 47 are not part of the header
+        assert_eq!(csp_id_t::from_ext2(0xFFFF_0000_0000_0000), id(CSP_PRIO_CRITICAL, 0, 0, 0, 0, 0));
+    }
+
+    #[test]
+    fn round_trip2() {
+        for &header in &[
+            id(CSP_PRIO_CRITICAL, 0, 0, 0, 0, 0),
+            id(CSP_PRIO_NORM, 1, 0x3FFF, 10, 63, CSP_FCRC32 as u8),
+            id(CSP_PRIO_LOW, 0x3FFF, 300, 63, 0, 0x3F),
+        ] {
+            let mut buf = [0u8; CSP_ID2_HEADER_SIZE];
+            header.encode(CSP_VERSION_2, &mut buf).unwrap();
+            assert_eq!(csp_id_t::decode(CSP_VERSION_2, &buf).unwrap(), header);
+        }
+    }
+
+    #[test]
+    fn out_of_range2() {
+        assert!(matches!(id(CSP_PRIO_NORM, 0x4000, 1, 0, 0, 0).ext2(), Err(CspError::InvalidAddress(0x4000))));
+        assert!(matches!(id(CSP_PRIO_NORM, 1, 2, 0, 64, 0).ext2(), Err(CspError::InvalidPort(64))));
+        // flags 0x40 fit a 1.x header but not the 6 bits of a 2.x one
+        let flags = id(CSP_PRIO_NORM, 1, 2, 0, 0, 0x40);
+        assert!(matches!(flags.ext2(), Err(CspError::InvalidHeader)));
+        assert!(flags.is_valid(CSP_VERSION_1));
+        // addresses above 31 only fit a 2.x header
+        let wide = id(CSP_PRIO_NORM, 100, 2, 0, 0, 0);
+        assert!(wide.is_valid(CSP_VERSION_2));
+        assert!(!wide.is_valid(CSP_VERSION_1));
+        assert!(matches!(csp_id_t::decode(CSP_VERSION_2, &[0u8; CSP_HEADER_LENGTH]), Err(CspError::InvalidHeader)));
+    }
+}
@@ -0,0 +1,399 @@
+// CAN Fragmentation Protocol (CFP)
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/interfaces/csp_if_can.c
+//
+// A packet is sent as a run of CAN frames with 29 bit extended identifiers:
+//
+//  28     24 23     19  18  17        10 9           0
+// +---------+---------+----+------------+-------------+
+// |   src   |   dst   |type|   remain   | identifier  |
+// +---------+---------+----+------------+-------------+
+//
+// The first frame (CFP_BEGIN) carries the CSP 1.x header, the payload length
+// and the first 2 payload bytes; the following frames (CFP_MORE) carry up to
+// 8 payload bytes each. `remain` counts down the frames still to come and
+// `identifier` is shared by all frames of one packet; the receiver keys its
+// reassembly on (src, identifier).
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{Duration, Instant};
+
+use socketcan::CANFrame;
+
+use super::*;
+
+pub const CFP_HOST_SIZE: u32 = 5;
+pub const CFP_TYPE_SIZE: u32 = 1;
+pub const CFP_REMAIN_SIZE: u32 = 8;
+pub const CFP_ID_SIZE: u32 = 10;
+
+/** CFP identifier - source address mask */
+pub const CFP_SRC_MASK: u32 = ((1 << CFP_HOST_SIZE) - 1) << (CFP_HOST_SIZE + CFP_TYPE_SIZE + CFP_REMAIN_SIZE + CFP_ID_SIZE);
+/** CFP identifier - destination address mask */
+pub const CFP_DST_MASK: u32 = ((1 << CFP_HOST_SIZE) - 1) << (CFP_TYPE_SIZE + CFP_REMAIN_SIZE + CFP_ID_SIZE);
+/** CFP identifier - frame type mask */
+pub const CFP_TYPE_MASK: u32 = ((1 << CFP_TYPE_SIZE) - 1) << (CFP_REMAIN_SIZE + CFP_ID_SIZE);
+/** CFP identifier - remaining frames mask */
+pub const CFP_REMAIN_MASK: u32 = ((1 << CFP_REMAIN_SIZE) - 1) << CFP_ID_SIZE;
+/** CFP identifier - packet identifier mask */
+pub const CFP_ID_MASK: u32 = (1 << CFP_ID_SIZE) - 1;
+
+pub const CFP_BEGIN: u32 = 0; // !< First frame of a packet
+pub const CFP_MORE: u32 = 1;  // !< Following frames
+
+// header (4) and length (2) in the first frame
+const CFP_OVERHEAD: usize = CSP_HEADER_LENGTH + 2;
+const CFP_FRAME_SIZE: usize = 8;
+
+/// Largest payload CFP can carry: 2 bytes in the first frame, 8 in each of up to 255 more
+pub const CFP_MAX_LENGTH: usize = (CFP_FRAME_SIZE - CFP_OVERHEAD) + ((1 << CFP_REMAIN_SIZE) - 1) * CFP_FRAME_SIZE;
+
+// per-packet identifier shared by all frames, wraps at 10 bits
+static CFP_IDENT: AtomicU16 = AtomicU16::new(0);
+
+fn cfp_place(value: u32, mask: u32) -> u32 {
+    (value << mask.trailing_zeros()) & mask
+}
+
+pub fn cfp_field(id: u32, mask: u32) -> u32 {
+    (id & mask) >> mask.trailing_zeros()
+}
+
+/// Build a 29 bit CFP identifier
+pub fn cfp_make_id(src: u16, dst: u16, frame_type: u32, remain: u8, ident: u16) -> u32 {
+    cfp_place(src as u32, CFP_SRC_MASK)
+        | cfp_place(dst as u32, CFP_DST_MASK)
+        | cfp_place(frame_type, CFP_TYPE_MASK)
+        | cfp_place(remain as u32, CFP_REMAIN_MASK)
+        | cfp_place(ident as u32, CFP_ID_MASK)
+}
+
+/// Split a packet into CFP frames addressed to `via` (or to the packet's own
+/// destination when `via` is CSP_NO_VIA_ADDRESS).
+///
+/// Only the 1.x header exists on CFP, so the packet's addresses must fit in 5 bits.
+/// socketcan only sets the extended-frame flag for identifiers above 0x7FF,
+/// which every CFP identifier is unless source and destination are both 0.
+pub fn csp8u_CAN(packet: &csp_packet_t, via: u16) -> Result<Vec<CANFrame>, CspError> {
+    if packet.length() > CFP_MAX_LENGTH {
+        return Err(CspError::TooLarge { length: packet.length(), max: CFP_MAX_LENGTH });
+    }
+    let dst = if via != CSP_NO_VIA_ADDRESS { via } else { packet.id.dst };
+    if dst as u32 > CSP_ID_HOST_MAX {
+        return Err(CspError::InvalidAddress(dst));
+    }
+    let header = packet.id.to_be_bytes()?;
+    let ident = CFP_IDENT.fetch_add(1, Ordering::Relaxed) & CFP_ID_MASK as u16;
+
+    let data = packet.data();
+    let first = data.len().min(CFP_FRAME_SIZE - CFP_OVERHEAD);
+    let rest = &data[first..];
+    let mut remain = rest.len().div_ceil(CFP_FRAME_SIZE) as u8;
+
+    let mut frames = Vec::with_capacity(1 + remain as usize);
+    let mut begin = [0u8; CFP_FRAME_SIZE];
+    begin[..CSP_HEADER_LENGTH].copy_from_slice(&header);
+    begin[CSP_HEADER_LENGTH..CFP_OVERHEAD].copy_from_slice(&(data.len() as u16).to_be_bytes());
+    begin[CFP_OVERHEAD..CFP_OVERHEAD + first].copy_from_slice(&data[..first]);
+    let id = cfp_make_id(packet.id.src, dst, CFP_BEGIN, remain, ident);
+    frames.push(CANFrame::new(id, &begin[..CFP_OVERHEAD + first], false, false).map_err(|_| CspError::InvalidHeader)?);
+
+    for chunk in rest.chunks(CFP_FRAME_SIZE) {
+        remain -= 1;
+        let id = cfp_make_id(packet.id.src, dst, CFP_MORE, remain, ident);
+        frames.push(CANFrame::new(id, chunk, false, false).map_err(|_| CspError::InvalidHeader)?);
+    }
+    Ok(frames)
+}
+
+/// How long a partly received packet waits for its next frame, like libcsp's PBUF_TIMEOUT_MS
+pub const CFP_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Packets reassembled at once at most, like libcsp's PBUF_ELEMENTS
+pub const CFP_PBUF_ELEMENTS: usize = 10;
+
+/// Reassembly counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct csp_can_rx_stats_t {
+    pub rx: u32,      // !< Packets completed
+    pub frame: u32,   // !< Frames that are not valid CFP (standard id, RTR, error, short BEGIN)
+    pub drop: u32,    // !< Partial packets discarded: lost frame, MORE without BEGIN, restarted identifier, no free context or buffer
+    pub overrun: u32, // !< Packets carrying more data, or more frames, than their header declared
+    pub timeout: u32, // !< Partial packets discarded because the next frame never came
+}
+
+// a packet being reassembled
+struct cfp_pbuf_t {
+    packet: csp_packet_t,
+    length: usize, // declared payload length
+    remain: u8,    // remain value of the last frame received
+    last_used: Instant,
+}
+
+/// CFP receive side: rebuilds packets from frames of several senders at once.
+///
+/// Every (source, identifier) pair gets its own context, so interleaved packets
+/// from different nodes do not disturb each other. A frame out of sequence
+/// discards its packet instead of corrupting it, and contexts that stop
+/// receiving frames are dropped after `timeout`. At most CFP_PBUF_ELEMENTS
+/// packets are reassembled at once, each in a buffer from `pool`.
+pub struct csp_can_rx_t {
+    pbufs: HashMap<(u16, u16), cfp_pbuf_t>,
+    timeout: Duration,
+    pool: csp_buffer_pool_t,
+    stats: csp_can_rx_stats_t,
+}
+
+impl csp_can_rx_t {
+    pub fn new(timeout: Duration, pool: csp_buffer_pool_t) -> csp_can_rx_t {
+        csp_can_rx_t {
+            pbufs: HashMap::with_capacity(CFP_PBUF_ELEMENTS),
+            timeout,
+            pool,
+            stats: csp_can_rx_stats_t::default(),
+        }
+    }
+
+    pub fn stats(&self) -> csp_can_rx_stats_t {
+        self.stats
+    }
+
+    /// Number of packets currently being reassembled
+    pub fn pending(&self) -> usize {
+        self.pbufs.len()
+    }
+
+    /// Drop contexts that have not seen a frame within the timeout
+    pub fn purge(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        let before = self.pbufs.len();
+        self.pbufs.retain(|_, pbuf| now.duration_since(pbuf.last_used) < timeout);
+        self.stats.timeout += (before - self.pbufs.len()) as u32;
+    }
+
+    /// Feed one received frame. Returns the packet it completes, if any.
+    pub fn rx_frame(&mut self, frame: &CANFrame) -> Option<csp_packet_t> {
+        let now = Instant::now();
+        self.purge(now);
+
+        if !frame.is_extended() || frame.is_rtr() || frame.is_error() {
+            self.stats.frame += 1;
+            return None;
+        }
+        let id = frame.id();
+        let key = (cfp_field(id, CFP_SRC_MASK) as u16, cfp_field(id, CFP_ID_MASK) as u16);
+        let remain = cfp_field(id, CFP_REMAIN_MASK) as u8;
+        let data = frame.data();
+
+        if cfp_field(id, CFP_TYPE_MASK) == CFP_BEGIN {
+            if self.pbufs.remove(&key).is_some() {
+                self.stats.drop += 1;
+            }
+            if data.len() < CFP_OVERHEAD {
+                self.stats.frame += 1;
+                return None;
+            }
+            let id = match csp_id_t::from_be_bytes(data) {
+                Ok(id) => id,
+                Err(_) => {
+                    self.stats.frame += 1;
+                    return None;
+                }
+            };
+            let length = u16::from_be_bytes([data[CSP_HEADER_LENGTH], data[CSP_HEADER_LENGTH + 1]]) as usize;
+            let first = &data[CFP_OVERHEAD..];
+            // the frame count has to match the declared length
+            let expected = length.saturating_sub(CFP_FRAME_SIZE - CFP_OVERHEAD).div_ceil(CFP_FRAME_SIZE);
+            if length > CFP_MAX_LENGTH || first.len() > length || expected != remain as usize {
+                self.stats.overrun += 1;
+                return None;
+            }
+            // a flood of BEGIN frames must not take every buffer
+            if self.pbufs.len() >= CFP_PBUF_ELEMENTS {
+                self.stats.drop += 1;
+                return None;
+            }
+            let mut packet = match self.pool.get(length) {
+                Ok(packet) => packet,
+                Err(_) => {
+                    self.stats.drop += 1;
+                    return None;
+                }
+            };
+            packet.id = id;
+            packet.append(first).ok()?;
+            let pbuf = cfp_pbuf_t { packet, length, remain, last_used: now };
+            return self.complete(key, pbuf);
+        }
+
+        let mut pbuf = match self.pbufs.remove(&key) {
+            Some(pbuf) => pbuf,
+            None => {
+                // the BEGIN frame was lost or timed out
+                self.stats.drop += 1;
+                return None;
+            }
+        };
+        if pbuf.remain == 0 || remain != pbuf.remain - 1 {
+            self.stats.drop += 1;
+            return None;
+        }
+        if pbuf.packet.length() + data.len() > pbuf.length {
+            self.stats.overrun += 1;
+            return None;
+        }
+        pbuf.packet.append(data).ok()?;
+        pbuf.remain = remain;
+        pbuf.last_used = now;
+        self.complete(key, pbuf)
+    }
+
+    // hand the packet out once its last frame is in, otherwise keep waiting
+    fn complete(&mut self, key: (u16, u16), pbuf: cfp_pbuf_t) -> Option<csp_packet_t> {
+        if pbuf.remain > 0 {
+            self.pbufs.insert(key, pbuf);
+            return None;
+        }
+        if pbuf.packet.length() != pbuf.length {
+            self.stats.drop += 1;
+            return None;
+        }
+        self.stats.rx += 1;
+        Some(pbuf.packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CSP::csp_test::*;
+
+    const BUFFERS: usize = 2 * CFP_PBUF_ELEMENTS;
+
+    fn pool() -> csp_buffer_pool_t {
+        csp_buffer_pool_t::new(BUFFERS, CFP_MAX_LENGTH)
+    }
+
+    // feed every frame, expecting a packet from the last one only
+    fn reassemble(rx: &mut csp_can_rx_t, frames: &[CANFrame]) -> csp_packet_t {
+        let (last, rest) = frames.split_last().unwrap();
+        for frame in rest {
+            assert!(rx.rx_frame(frame).is_none());
+        }
+        rx.rx_frame(last).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rx = csp_can_rx_t::new(CFP_TIMEOUT, pool());
+        for &(length, count) in &[(0, 1), (1, 1), (2, 1), (3, 2), (10, 2), (11, 3), (CFP_MAX_LENGTH, 256)] {
+            let sent = packet_from(3, &payload(length));
+            let frames = csp8u_CAN(&sent, CSP_NO_VIA_ADDRESS).unwrap();
+            assert_eq!(frames.len(), count, "length {}", length);
+            assert_eq!(cfp_field(frames[0].id(), CFP_DST_MASK), CSP_TEST_ID.dst as u32);
+            assert_eq!(cfp_field(frames[0].id(), CFP_REMAIN_MASK) as usize, count - 1);
+            let received = reassemble(&mut rx, &frames);
+            assert_eq!(received.id, sent.id);
+            assert_eq!(received.data(), sent.data());
+        }
+        assert_eq!(rx.stats().rx, 7);
+        assert!(csp8u_CAN(&packet_from(3, &payload(CFP_MAX_LENGTH + 1)), CSP_NO_VIA_ADDRESS).is_err());
+    }
+
+    #[test]
+    fn via() {
+        let frames = csp8u_CAN(&packet_from(3, &payload(4)), 9).unwrap();
+        assert_eq!(cfp_field(frames[0].id(), CFP_DST_MASK), 9);
+        assert_eq!(cfp_field(frames[0].id(), CFP_SRC_MASK), 3);
+    }
+
+    #[test]
+    fn interleaved_senders() {
+        let mut rx = csp_can_rx_t::new(CFP_TIMEOUT, pool());
+        let (a, b) = (packet_from(3, &payload(20)), packet_from(4, &payload(30)));
+        let a_frames = csp8u_CAN(&a, CSP_NO_VIA_ADDRESS).unwrap();
+        let b_frames = csp8u_CAN(&b, CSP_NO_VIA_ADDRESS).unwrap();
+        let mut received = Vec::new();
+        for i in 0..b_frames.len() {
+            for frames in [&a_frames, &b_frames] {
+                if let Some(packet) = frames.get(i).and_then(|frame| rx.rx_frame(frame)) {
+                    received.push(packet);
+                }
+            }
+        }
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].data(), a.data());
+        assert_eq!(received[1].data(), b.data());
+        assert_eq!(rx.pending(), 0);
+    }
+
+    #[test]
+    fn lost_more_frame() {
+        let pool = pool();
+        let mut rx = csp_can_rx_t::new(CFP_TIMEOUT, pool.clone());
+        let frames = csp8u_CAN(&packet_from(3, &payload(20)), CSP_NO_VIA_ADDRESS).unwrap();
+        assert!(rx.rx_frame(&frames[0]).is_none());
+        assert!(rx.rx_frame(&frames[2]).is_none());
+        assert!(rx.rx_frame(&frames[3]).is_none());
+        assert_eq!(rx.stats().drop, 2);
+        assert_eq!(rx.pending(), 0);
+        assert_eq!(pool.remaining(), BUFFERS);
+
+        // the next packet from the same sender is not affected
+        let sent = packet_from(3, &payload(20));
+        let frames = csp8u_CAN(&sent, CSP_NO_VIA_ADDRESS).unwrap();
+        assert_eq!(reassemble(&mut rx, &frames).data(), sent.data());
+    }
+
+    #[test]
+    fn bad_remain() {
+        let mut rx = csp_can_rx_t::new(CFP_TIMEOUT, pool());
+        let frames = csp8u_CAN(&packet_from(3, &payload(20)), CSP_NO_VIA_ADDRESS).unwrap();
+        let id = frames[0].id();
+        let remain = cfp_field(id, CFP_REMAIN_MASK) as u8;
+
+        // BEGIN announcing one frame more than its length needs
+        let ident = cfp_field(id, CFP_ID_MASK) as u16;
+        let begin = CANFrame::new(cfp_make_id(3, CSP_TEST_ID.dst, CFP_BEGIN, remain + 1, ident), frames[0].data(), false, false).unwrap();
+        assert!(rx.rx_frame(&begin).is_none());
+        assert_eq!(rx.stats().overrun, 1);
+        assert_eq!(rx.pending(), 0);
+
+        // MORE repeating a remain count
+        assert!(rx.rx_frame(&frames[0]).is_none());
+        assert!(rx.rx_frame(&frames[1]).is_none());
+        assert!(rx.rx_frame(&frames[1]).is_none());
+        assert_eq!(rx.stats().drop, 1);
+        assert_eq!(rx.pending(), 0);
+    }
+
+    #[test]
+    fn timeout_purge() {
+        let pool = pool();
+        let mut rx = csp_can_rx_t::new(Duration::from_millis(50), pool.clone());
+        let frames = csp8u_CAN(&packet_from(3, &payload(20)), CSP_NO_VIA_ADDRESS).unwrap();
+        assert!(rx.rx_frame(&frames[0]).is_none());
+        assert_eq!(rx.pending(), 1);
+        assert_eq!(pool.remaining(), BUFFERS - 1);
+
+        rx.purge(Instant::now() + Duration::from_millis(100));
+        assert_eq!(rx.pending(), 0);
+        assert_eq!(rx.stats().timeout, 1);
+        assert_eq!(pool.remaining(), BUFFERS);
+        assert!(rx.rx_frame(&frames[1]).is_none());
+        assert_eq!(rx.stats().drop, 1);
+    }
+
+    #[test]
+    fn begin_flood() {
+        let pool = pool();
+        let mut rx = csp_can_rx_t::new(CFP_TIMEOUT, pool.clone());
+        for _ in 0..CFP_PBUF_ELEMENTS + 5 {
+            let frames = csp8u_CAN(&packet_from(3, &payload(20)), CSP_NO_VIA_ADDRESS).unwrap();
+            assert!(rx.rx_frame(&frames[0]).is_none());
+        }
+        assert_eq!(rx.pending(), CFP_PBUF_ELEMENTS);
+        assert_eq!(rx.stats().drop, 5);
+        assert_eq!(pool.remaining(), BUFFERS - CFP_PBUF_ELEMENTS);
+    }
+}
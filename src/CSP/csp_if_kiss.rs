@@ -0,0 +1,282 @@
+// KISS framing for serial links
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/interfaces/csp_if_kiss.c
+//
+// A frame is FEND, the TNC data command, the escaped contents and FEND again.
+// The contents are the CSP header, the payload and a CRC32 of the payload,
+// which libcsp adds to every KISS frame whether or not CSP_FCRC32 is set.
+// FEND and FESC inside the contents are sent as FESC TFEND and FESC TFESC.
+
+use super::*;
+
+pub const FEND: u8 = 0xC0;     // !< Frame end
+pub const FESC: u8 = 0xDB;     // !< Frame escape
+pub const TFEND: u8 = 0xDC;    // !< Transposed frame end
+pub const TFESC: u8 = 0xDD;    // !< Transposed frame escape
+pub const TNC_DATA: u8 = 0x00; // !< KISS data frame on port 0
+
+/// Encode a packet as one KISS frame
+pub fn csp16u_UART(packet: &csp_packet_t, version: csp_version_t) -> Result<Vec<u8>, CspError> {
+    let mut header = [0u8; CSP_ID2_HEADER_SIZE];
+    let size = packet.id.encode(version, &mut header)?;
+    let crc = csp_crc32_memory(packet.data()).to_be_bytes();
+
+    let contents = header[..size].iter().chain(packet.data()).chain(&crc);
+    let mut frame = Vec::with_capacity(2 * (size + packet.length() + CSP_CRC32_LENGTH) + 3);
+    frame.push(FEND);
+    frame.push(TNC_DATA);
+    for &byte in contents {
+        match byte {
+            FEND => frame.extend_from_slice(&[FESC, TFEND]),
+            FESC => frame.extend_from_slice(&[FESC, TFESC]),
+            _ => frame.push(byte),
+        }
+    }
+    frame.push(FEND);
+    Ok(frame)
+}
+
+/// Decoder counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct csp_kiss_stats_t {
+    pub rx: u32,       // !< Packets decoded
+    pub frame: u32,    // !< Frames dropped: not a data frame, too short, bad escape or header
+    pub rx_error: u32, // !< Frames dropped on CRC32 mismatch
+    pub overrun: u32,  // !< Frames dropped for exceeding the maximum length
+    pub drop: u32,     // !< Frames dropped for lack of a free buffer
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum csp_kiss_mode_t {
+    KISS_MODE_NOT_STARTED, // waiting for FEND, everything else is line noise
+    KISS_MODE_STARTED,     // after FEND, next byte is the command
+    KISS_MODE_RECEIVING,
+    KISS_MODE_ESCAPED,
+    KISS_MODE_SKIP_FRAME,  // discard until the next FEND
+}
+
+/// Streaming KISS decoder, fed one byte at a time from a serial port.
+///
+/// Anything outside a frame is ignored, as are frames that are not TNC data
+/// frames, that fail their CRC32 or that grow beyond `mtu` payload bytes.
+/// Decoded packets are copied into buffers from `pool`.
+pub struct csp_kiss_decoder_t {
+    version: csp_version_t,
+    pool: csp_buffer_pool_t,
+    mode: csp_kiss_mode_t,
+    buf: Vec<u8>,
+    max_length: usize,
+    stats: csp_kiss_stats_t,
+}
+
+impl csp_kiss_decoder_t {
+    /// Decoder for `version` headers and payloads of at most `mtu` bytes
+    pub fn new(version: csp_version_t, mtu: usize, pool: csp_buffer_pool_t) -> csp_kiss_decoder_t {
+        let max_length = version.header_size() + mtu + CSP_CRC32_LENGTH;
+        csp_kiss_decoder_t {
+            version,
+            pool,
+            mode: csp_kiss_mode_t::KISS_MODE_NOT_STARTED,
+            buf: Vec::with_capacity(max_length),
+            max_length,
+            stats: csp_kiss_stats_t::default(),
+        }
+    }
+
+    pub fn stats(&self) -> csp_kiss_stats_t {
+        self.stats
+    }
+
+    /// Feed one byte. Returns the packet it completes, if any.
+    pub fn feed(&mut self, byte: u8) -> Option<csp_packet_t> {
+        use self::csp_kiss_mode_t::*;
+
+        match (self.mode, byte) {
+            (KISS_MODE_NOT_STARTED, FEND) => self.mode = KISS_MODE_STARTED,
+            (KISS_MODE_NOT_STARTED, _) => {}
+            // back to back FENDs are empty frames
+            (KISS_MODE_STARTED, FEND) => {}
+            (KISS_MODE_STARTED, TNC_DATA) => {
+                self.buf.clear();
+                self.mode = KISS_MODE_RECEIVING;
+            }
+            (KISS_MODE_STARTED, _) => {
+                self.stats.frame += 1;
+                self.mode = KISS_MODE_SKIP_FRAME;
+            }
+            (KISS_MODE_SKIP_FRAME, FEND) => self.mode = KISS_MODE_STARTED,
+            (KISS_MODE_SKIP_FRAME, _) => {}
+            (KISS_MODE_RECEIVING, FEND) => {
+                // this FEND also opens the next frame
+                self.mode = KISS_MODE_STARTED;
+                return self.finish();
+            }
+            (KISS_MODE_RECEIVING, FESC) => self.mode = KISS_MODE_ESCAPED,
+            (KISS_MODE_RECEIVING, _) => self.push(byte),
+            (KISS_MODE_ESCAPED, TFEND) => {
+                self.mode = KISS_MODE_RECEIVING;
+                self.push(FEND);
+            }
+            (KISS_MODE_ESCAPED, TFESC) => {
+                self.mode = KISS_MODE_RECEIVING;
+                self.push(FESC);
+            }
+            (KISS_MODE_ESCAPED, _) => {
+                self.stats.frame += 1;
+                self.mode = if byte == FEND { KISS_MODE_STARTED } else { KISS_MODE_SKIP_FRAME };
+            }
+        }
+        None
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.buf.len() == self.max_length {
+            self.stats.overrun += 1;
+            self.mode = csp_kiss_mode_t::KISS_MODE_SKIP_FRAME;
+            return;
+        }
+        self.buf.push(byte);
+    }
+
+    fn finish(&mut self) -> Option<csp_packet_t> {
+        let size = self.version.header_size();
+        if self.buf.len() < size + CSP_CRC32_LENGTH {
+            self.stats.frame += 1;
+            return None;
+        }
+        let id = match csp_id_t::decode(self.version, &self.buf) {
+            Ok(id) => id,
+            Err(_) => {
+                self.stats.frame += 1;
+                return None;
+            }
+        };
+        // check the CRC in place, so only the payload needs a buffer
+        let (data, crc) = self.buf[size..].split_at(self.buf.len() - size - CSP_CRC32_LENGTH);
+        if crc != csp_crc32_memory(data).to_be_bytes() {
+            self.stats.rx_error += 1;
+            return None;
+        }
+        match self.pool.get_slice(id, data) {
+            Ok(packet) => {
+                self.stats.rx += 1;
+                Some(packet)
+            }
+            Err(_) => {
+                self.stats.drop += 1;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CSP::csp_test::*;
+    use crate::CSP::csp_version_t::*;
+
+    fn decoder(mtu: usize) -> csp_kiss_decoder_t {
+        csp_kiss_decoder_t::new(CSP_VERSION_1, mtu, csp_buffer_pool_t::new(4, CSP_BUFFER_SIZE))
+    }
+
+    fn decode(decoder: &mut csp_kiss_decoder_t, bytes: &[u8]) -> Vec<csp_packet_t> {
+        bytes.iter().filter_map(|&byte| decoder.feed(byte)).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let sent = packet(b"hello");
+        let mut dec = decoder(CSP_BUFFER_SIZE);
+        let got = decode(&mut dec, &csp16u_UART(&sent, CSP_VERSION_1).unwrap());
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].id, sent.id);
+        assert_eq!(got[0].data(), b"hello");
+        assert_eq!(dec.stats(), csp_kiss_stats_t { rx: 1, ..Default::default() });
+    }
+
+    #[test]
+    fn full_mtu() {
+        let data = payload(CSP_BUFFER_SIZE);
+        let mut dec = decoder(CSP_BUFFER_SIZE);
+        let got = decode(&mut dec, &csp16u_UART(&packet(&data), CSP_VERSION_1).unwrap());
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].data(), &data[..]);
+        assert_eq!(dec.stats(), csp_kiss_stats_t { rx: 1, ..Default::default() });
+    }
+
+    #[test]
+    fn escaped_payload() {
+        let data = [FEND, FESC, TFEND, TFESC, FESC, FEND, 0x00];
+        let frame = csp16u_UART(&packet(&data), CSP_VERSION_1).unwrap();
+        // only the first and the last byte are frame ends
+        assert_eq!(frame.iter().filter(|&&b| b == FEND).count(), 2);
+        let got = decode(&mut decoder(CSP_BUFFER_SIZE), &frame);
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].data(), &data);
+    }
+
+    #[test]
+    fn garbage_between_frames() {
+        let mut stream = b"noise".to_vec();
+        stream.extend(csp16u_UART(&packet(b"one"), CSP_VERSION_1).unwrap());
+        stream.extend(b"more noise");
+        stream.extend(csp16u_UART(&packet(b"two"), CSP_VERSION_1).unwrap());
+        let mut dec = decoder(CSP_BUFFER_SIZE);
+        let got = decode(&mut dec, &stream);
+        assert_eq!(got.iter().map(|p| p.data()).collect::<Vec<_>>(), [b"one", b"two"]);
+        // noise before the first FEND is not even a frame
+        assert_eq!(dec.stats().frame, 1);
+    }
+
+    #[test]
+    fn back_to_back_frames() {
+        // frames sharing a FEND, and empty frames in between
+        let mut stream = csp16u_UART(&packet(b"one"), CSP_VERSION_1).unwrap();
+        let two = csp16u_UART(&packet(b"two"), CSP_VERSION_1).unwrap();
+        stream.extend(&two[1..]);
+        stream.extend([FEND, FEND]);
+        stream.extend(&two);
+        let mut dec = decoder(CSP_BUFFER_SIZE);
+        let got = decode(&mut dec, &stream);
+        assert_eq!(got.iter().map(|p| p.data()).collect::<Vec<_>>(), [b"one", b"two", b"two"]);
+        assert_eq!(dec.stats(), csp_kiss_stats_t { rx: 3, ..Default::default() });
+    }
+
+    #[test]
+    fn oversize_frame() {
+        let mut stream = csp16u_UART(&packet(&[0x55; 9]), CSP_VERSION_1).unwrap();
+        stream.extend(csp16u_UART(&packet(&[0x55; 8]), CSP_VERSION_1).unwrap());
+        let mut dec = decoder(8);
+        let got = decode(&mut dec, &stream);
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].length(), 8);
+        assert_eq!(dec.stats().overrun, 1);
+    }
+
+    #[test]
+    fn bad_escape() {
+        let mut stream = csp16u_UART(&packet(b"one"), CSP_VERSION_1).unwrap();
+        // FESC followed by something other than TFEND or TFESC
+        let at = stream.len() - 3;
+        stream.insert(at, FESC);
+        stream.extend(csp16u_UART(&packet(b"two"), CSP_VERSION_1).unwrap());
+        let mut dec = decoder(CSP_BUFFER_SIZE);
+        let got = decode(&mut dec, &stream);
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].data(), b"two");
+        assert_eq!(dec.stats().frame, 1);
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        let mut stream = csp16u_UART(&packet(b"one"), CSP_VERSION_1).unwrap();
+        // first payload byte, after FEND, the command and the 4 byte header
+        stream[6] ^= 0x01;
+        stream.extend(csp16u_UART(&packet(b"two"), CSP_VERSION_1).unwrap());
+        let mut dec = decoder(CSP_BUFFER_SIZE);
+        let got = decode(&mut dec, &stream);
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].data(), b"two");
+        assert_eq!(dec.stats().rx_error, 1);
+    }
+}
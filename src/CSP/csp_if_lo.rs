@@ -0,0 +1,140 @@
+// Loopback interface
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/interfaces/csp_if_lo.c
+//
+// Packets transmitted on the loopback come straight back out of receive(),
+// so a client and a server in the same process talk through the router
+// exactly as they would over a real link. The copies come from the node's pool.
+
+use std::io;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::*;
+
+/// Default name of the loopback interface, as in libcsp
+pub const CSP_IF_LOOPBACK_NAME: &str = "LOOP";
+
+/// In-process loopback link
+pub struct csp_if_lo_t {
+    pool: csp_buffer_pool_t,
+    tx: Mutex<mpsc::Sender<csp_packet_t>>,
+    rx: Mutex<mpsc::Receiver<csp_packet_t>>,
+    stats: csp_iface_stats_t,
+}
+
+impl csp_if_lo_t {
+    /// Loopback for `node`, copying packets into its buffers
+    pub fn new(node: &csp_node_t) -> csp_if_lo_t {
+        let (tx, rx) = mpsc::channel();
+        csp_if_lo_t {
+            pool: node.buffer_pool().clone(),
+            tx: Mutex::new(tx),
+            rx: Mutex::new(rx),
+            stats: csp_iface_stats_t::default(),
+        }
+    }
+}
+
+impl CspInterface for csp_if_lo_t {
+    fn name(&self) -> &str {
+        CSP_IF_LOOPBACK_NAME
+    }
+
+    // nothing goes over a wire, any packet that fits a buffer fits
+    fn mtu(&self) -> usize {
+        self.pool.size()
+    }
+
+    /// Queue a copy of `packet` for receive(). `via` is ignored.
+    /// Fails with NoBuffers when the pool is empty.
+    fn transmit(&self, packet: &csp_packet_t, _via: u16) -> Result<(), CspError> {
+        let copy = self
+            .pool
+            .get_slice(packet.id, packet.data())
+            .inspect_err(|_| csp_iface_stats_t::add(&self.stats.tx_error, 1))?;
+        // the receiver lives as long as we do, so this should not fail
+        self.tx
+            .lock()
+            .unwrap()
+            .send(copy)
+            .map_err(|_| CspError::Io(io::ErrorKind::BrokenPipe.into()))
+            .inspect_err(|_| csp_iface_stats_t::add(&self.stats.tx_error, 1))?;
+        self.stats.count_tx(packet.length());
+        Ok(())
+    }
+
+    fn receive(&self, timeout: Duration) -> Result<csp_packet_t, CspError> {
+        let packet = self.rx.lock().unwrap().recv_timeout(timeout).map_err(|_| CspError::Timeout)?;
+        self.stats.count_rx(packet.length());
+        Ok(packet)
+    }
+
+    fn stats(&self) -> &csp_iface_stats_t {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::thread;
+
+    use super::*;
+    use crate::CSP::csp_prio_t::*;
+    use crate::CSP::csp_service_ports_t::*;
+    use crate::CSP::csp_test::*;
+
+    const WAIT: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn transmit_receive() {
+        let node = csp_node_t::new(csp_conf_t { buffers: 1, ..Default::default() }).unwrap();
+        let lo = csp_if_lo_t::new(&node);
+        assert!(matches!(lo.receive(Duration::from_millis(10)), Err(CspError::Timeout)));
+
+        let sent = packet(b"hello");
+        lo.transmit(&sent, 7).unwrap();
+        assert_eq!(node.buffer_pool().remaining(), 0);
+        assert!(matches!(lo.transmit(&sent, CSP_NO_VIA_ADDRESS), Err(CspError::NoBuffers)));
+
+        let received = lo.receive(WAIT).unwrap();
+        assert_eq!((received.id, received.data()), (sent.id, sent.data()));
+        drop(received);
+        assert_eq!(node.buffer_pool().remaining(), 1);
+
+        let stats = lo.stats();
+        assert_eq!((stats.tx.load(Ordering::Relaxed), stats.rx.load(Ordering::Relaxed)), (1, 1));
+        assert_eq!(stats.tx_error.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.rxbytes.load(Ordering::Relaxed), 5);
+        node.stop();
+    }
+
+    // a ping client and server on one node, talking through the router
+    #[test]
+    fn ping() {
+        let node = csp_node_t::new(csp_conf_t::default()).unwrap();
+        node.route_start_task().unwrap();
+        let socket = node.socket(CSP_SO_NONE).unwrap();
+        socket.bind(CSP_PING.into()).unwrap();
+        socket.listen(5).unwrap();
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let conn = socket.accept(WAIT).unwrap();
+                let request = conn.read(WAIT).unwrap();
+                conn.send(csp_packet_t::from_slice(conn.idout(), request.data())).unwrap();
+            });
+            let conn = node.connect(CSP_PRIO_NORM, node.address(), CSP_PING.into(), Duration::ZERO, CSP_O_NONE).unwrap();
+            conn.send(packet(&payload(100))).unwrap();
+            let reply = conn.read(WAIT).unwrap();
+            assert_eq!(reply.data(), &payload(100)[..]);
+            assert_eq!((reply.id.src, reply.id.sport, reply.id.dport), (node.address(), CSP_PING as u8, conn.sport()));
+        });
+
+        let lo = node.iflist().get_by_name(CSP_IF_LOOPBACK_NAME).unwrap().clone();
+        assert_eq!(lo.stats().tx.load(Ordering::Relaxed), 2);
+        assert_eq!(lo.stats().rx.load(Ordering::Relaxed), 2);
+        node.stop();
+    }
+}
@@ -0,0 +1,217 @@
+// UDP interface
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/interfaces/csp_if_udp.c
+//
+// One CSP packet per datagram: the header (big endian) followed by the
+// payload, no framing and no checksum of its own. Packets go to a single
+// configured peer; datagrams from any sender on the local port are accepted
+// and copied into a buffer from the node's pool.
+
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::*;
+
+/// Port libcsp uses on both ends when none is configured
+pub const CSP_IF_UDP_PORT: u16 = 9600;
+
+/// UDP interface settings, like libcsp's csp_if_udp_conf_t
+#[derive(Debug, Clone)]
+pub struct csp_if_udp_conf_t {
+    pub name: String,           // !< Interface name
+    pub host: String,           // !< Peer host name or address
+    pub lport: u16,             // !< Local port to receive on
+    pub rport: u16,             // !< Peer port to send to
+    pub version: csp_version_t, // !< Header version spoken by the peer
+    pub mtu: usize,             // !< Largest payload sent or accepted
+}
+
+impl Default for csp_if_udp_conf_t {
+    fn default() -> csp_if_udp_conf_t {
+        csp_if_udp_conf_t {
+            name: String::from("UDP"),
+            host: String::from("127.0.0.1"),
+            lport: CSP_IF_UDP_PORT,
+            rport: CSP_IF_UDP_PORT,
+            version: csp_version_t::default(),
+            mtu: CSP_BUFFER_SIZE,
+        }
+    }
+}
+
+/// UDP link to one peer
+pub struct csp_if_udp_t {
+    conf: csp_if_udp_conf_t,
+    peer: SocketAddr,
+    socket: UdpSocket,
+    pool: csp_buffer_pool_t,
+    rx_buf: Mutex<Vec<u8>>, // also keeps concurrent receive() calls from fighting over the read timeout
+    stats: csp_iface_stats_t,
+}
+
+impl csp_if_udp_t {
+    /// Resolve the peer and bind the local port, receiving into `node`'s buffers
+    pub fn open(node: &csp_node_t, conf: csp_if_udp_conf_t) -> Result<csp_if_udp_t, CspError> {
+        let peer = (conf.host.as_str(), conf.rport).to_socket_addrs()?.next().ok_or_else(|| {
+            CspError::Io(std::io::Error::new(std::io::ErrorKind::NotFound, format!("cannot resolve {}", conf.host)))
+        })?;
+        let local: SocketAddr = if peer.is_ipv4() {
+            ([0, 0, 0, 0], conf.lport).into()
+        } else {
+            ([0u16; 8], conf.lport).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        // one byte more than we accept, to tell a full datagram from a truncated one
+        let rx_buf = vec![0u8; conf.version.header_size() + conf.mtu + 1];
+        Ok(csp_if_udp_t {
+            conf,
+            peer,
+            socket,
+            pool: node.buffer_pool().clone(),
+            rx_buf: Mutex::new(rx_buf),
+            stats: csp_iface_stats_t::default(),
+        })
+    }
+
+    pub fn conf(&self) -> &csp_if_udp_conf_t {
+        &self.conf
+    }
+
+    /// Address the local socket is bound to
+    pub fn local_addr(&self) -> Result<SocketAddr, CspError> {
+        Ok(self.socket.local_addr()?)
+    }
+}
+
+impl CspInterface for csp_if_udp_t {
+    fn name(&self) -> &str {
+        &self.conf.name
+    }
+
+    fn mtu(&self) -> usize {
+        self.conf.mtu
+    }
+
+    /// Send `packet` to the configured peer. `via` is ignored, the peer is the
+    /// only node on the link.
+    fn transmit(&self, packet: &csp_packet_t, _via: u16) -> Result<(), CspError> {
+        let result = packet.to_bytes(self.conf.version).and_then(|datagram| {
+            self.socket.send_to(&datagram, self.peer)?;
+            Ok(())
+        });
+        match result {
+            Ok(()) => self.stats.count_tx(packet.length()),
+            Err(_) => csp_iface_stats_t::add(&self.stats.tx_error, 1),
+        }
+        result
+    }
+
+    /// Wait up to `timeout` for the next datagram carrying a packet.
+    /// Datagrams too short for a header or longer than the MTU are dropped,
+    /// and so are datagrams that find no free buffer; they do not extend the wait.
+    fn receive(&self, timeout: Duration) -> Result<csp_packet_t, CspError> {
+        let mut buf = self.rx_buf.lock().unwrap();
+        let deadline = Instant::now() + timeout;
+        loop {
+            // a zero timeout would mean blocking forever
+            let remaining = deadline.saturating_duration_since(Instant::now()).max(Duration::from_millis(1));
+            self.socket.set_read_timeout(Some(remaining))?;
+            let (n, _) = self.socket.recv_from(&mut buf)?;
+            if n == buf.len() {
+                csp_iface_stats_t::add(&self.stats.rx_error, 1);
+                continue;
+            }
+            match self.pool.get_bytes(self.conf.version, &buf[..n]) {
+                Ok(packet) => {
+                    self.stats.count_rx(packet.length());
+                    return Ok(packet);
+                }
+                Err(CspError::NoBuffers) | Err(CspError::TooLarge { .. }) => csp_iface_stats_t::add(&self.stats.drop, 1),
+                Err(_) => csp_iface_stats_t::add(&self.stats.frame, 1),
+            }
+        }
+    }
+
+    fn stats(&self) -> &csp_iface_stats_t {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use super::*;
+
+    // a port nobody is bound to right now
+    fn free_port() -> u16 {
+        UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    fn pair(node: &csp_node_t, version: csp_version_t) -> (csp_if_udp_t, csp_if_udp_t) {
+        let (pa, pb) = (free_port(), free_port());
+        let conf = |lport, rport| csp_if_udp_conf_t { lport, rport, version, ..Default::default() };
+        (csp_if_udp_t::open(node, conf(pa, pb)).unwrap(), csp_if_udp_t::open(node, conf(pb, pa)).unwrap())
+    }
+
+    #[test]
+    fn round_trip() {
+        let node = csp_node_t::new(csp_conf_t::default()).unwrap();
+        for &version in &[csp_version_t::CSP_VERSION_1, csp_version_t::CSP_VERSION_2] {
+            let (a, b) = pair(&node, version);
+            let id = csp_id_t { src: 1, dst: 2, dport: 10, sport: 20, ..Default::default() };
+            a.transmit(&csp_packet_t::from_slice(id, b"ping"), CSP_NO_VIA_ADDRESS).unwrap();
+            let got = b.receive(Duration::from_secs(1)).unwrap();
+            assert_eq!(got.id, id);
+            assert_eq!(got.data(), b"ping");
+
+            let reply = csp_id_t { src: 2, dst: 1, dport: 20, sport: 10, ..Default::default() };
+            b.transmit(&csp_packet_t::from_slice(reply, b"pong"), CSP_NO_VIA_ADDRESS).unwrap();
+            let got = a.receive(Duration::from_secs(1)).unwrap();
+            assert_eq!(got.id, reply);
+            assert_eq!(got.data(), b"pong");
+
+            assert_eq!(a.stats().tx.load(Ordering::Relaxed), 1);
+            assert_eq!(b.stats().rx.load(Ordering::Relaxed), 1);
+        }
+        node.stop();
+    }
+
+    #[test]
+    fn receive_timeout() {
+        let node = csp_node_t::new(csp_conf_t::default()).unwrap();
+        let (a, _b) = pair(&node, csp_version_t::CSP_VERSION_1);
+        let start = Instant::now();
+        assert!(matches!(a.receive(Duration::from_millis(100)), Err(CspError::Timeout)));
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        // a zero timeout must not block forever
+        assert!(matches!(a.receive(Duration::ZERO), Err(CspError::Timeout)));
+        node.stop();
+    }
+
+    #[test]
+    fn receive_timeout_with_junk() {
+        let node = csp_node_t::new(csp_conf_t::default()).unwrap();
+        let (a, _b) = pair(&node, csp_version_t::CSP_VERSION_1);
+        let junk = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let to = a.local_addr().unwrap();
+        let sending = AtomicBool::new(true);
+
+        // datagrams too short for a header keep coming, but none is a packet
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                while sending.load(Ordering::Relaxed) {
+                    junk.send_to(&[0], to).unwrap();
+                    thread::sleep(Duration::from_millis(10));
+                }
+            });
+            let start = Instant::now();
+            assert!(matches!(a.receive(Duration::from_millis(200)), Err(CspError::Timeout)));
+            assert!(start.elapsed() < Duration::from_secs(1));
+            sending.store(false, Ordering::Relaxed);
+        });
+        assert!(a.stats().frame.load(Ordering::Relaxed) > 0);
+        node.stop();
+    }
+}
@@ -0,0 +1,231 @@
+// ZeroMQ hub interface
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/interfaces/csp_if_zmqhub.c
+//
+// Every node connects a PUB socket to the zmqproxy's subscribe port and a SUB
+// socket to its publish port; the proxy forwards everything to everyone.
+// A message is one prefix byte, the CSP 1.x header (big endian) and the
+// payload. The prefix is the next hop address, so nodes subscribe to their own
+// address and the proxy only sends them what is addressed to them.
+//
+//  +---------+------------------+---------+
+//  | via/dst | header (4 bytes) | payload |
+//  +---------+------------------+---------+
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::*;
+
+pub const CSP_ZMQPROXY_SUBSCRIBE_PORT: u16 = 6000; // !< zmqproxy port nodes publish to
+pub const CSP_ZMQPROXY_PUBLISH_PORT: u16 = 6001;   // !< zmqproxy port nodes subscribe to
+
+// prefix byte plus the 1.x header
+const CSP_ZMQHUB_OVERHEAD: usize = 1 + CSP_HEADER_LENGTH;
+
+/// Build a tcp endpoint, like csp_zmqhub_make_endpoint()
+pub fn csp_zmqhub_make_endpoint(host: &str, port: u16) -> String {
+    format!("tcp://{}:{}", host, port)
+}
+
+/// ZMQ hub interface settings
+#[derive(Debug, Clone)]
+pub struct csp_if_zmqhub_conf_t {
+    pub name: String,               // !< Interface name
+    pub publish_endpoint: String,   // !< Where to send, the proxy's subscribe port
+    pub subscribe_endpoint: String, // !< Where to receive from, the proxy's publish port
+    pub rxfilter: Vec<u8>,          // !< Addresses to receive; empty receives everything
+    pub write_timeout: Duration,    // !< Give up on a message not queued within this time
+    pub mtu: usize,                 // !< Largest payload sent or accepted
+}
+
+impl csp_if_zmqhub_conf_t {
+    /// Settings for node `addr` on the proxy at `host`, like csp_zmqhub_init().
+    /// CSP_NO_VIA_ADDRESS as `addr` receives all traffic.
+    pub fn new(addr: u16, host: &str) -> csp_if_zmqhub_conf_t {
+        csp_if_zmqhub_conf_t {
+            name: String::from("ZMQHUB"),
+            publish_endpoint: csp_zmqhub_make_endpoint(host, CSP_ZMQPROXY_SUBSCRIBE_PORT),
+            subscribe_endpoint: csp_zmqhub_make_endpoint(host, CSP_ZMQPROXY_PUBLISH_PORT),
+            rxfilter: if addr == CSP_NO_VIA_ADDRESS { Vec::new() } else { vec![addr as u8] },
+            write_timeout: Duration::from_secs(1),
+            mtu: CSP_BUFFER_SIZE,
+        }
+    }
+}
+
+/// Connection to a zmqproxy
+pub struct csp_if_zmqhub_t {
+    conf: csp_if_zmqhub_conf_t,
+    // zmq sockets must not be used from two threads at once
+    publisher: Mutex<zmq::Socket>,
+    subscriber: Mutex<zmq::Socket>,
+    pool: csp_buffer_pool_t,
+    stats: csp_iface_stats_t,
+}
+
+// zmq wants timeouts in whole milliseconds, -1 meaning forever
+fn csp_zmqhub_timeout(timeout: Duration) -> i32 {
+    timeout.as_millis().min(i32::MAX as u128) as i32
+}
+
+impl csp_if_zmqhub_t {
+    /// Connect both sockets and subscribe to `conf.rxfilter`, receiving into `node`'s buffers
+    pub fn open(node: &csp_node_t, conf: csp_if_zmqhub_conf_t) -> Result<csp_if_zmqhub_t, CspError> {
+        for &addr in &conf.rxfilter {
+            if addr as u32 > CSP_ID_HOST_MAX {
+                return Err(CspError::InvalidAddress(addr as u16));
+            }
+        }
+        let context = zmq::Context::new();
+
+        let publisher = context.socket(zmq::PUB)?;
+        publisher.set_sndtimeo(csp_zmqhub_timeout(conf.write_timeout))?;
+        // do not hang on close waiting for a proxy that is gone
+        publisher.set_linger(0)?;
+        publisher.connect(&conf.publish_endpoint)?;
+
+        let subscriber = context.socket(zmq::SUB)?;
+        if conf.rxfilter.is_empty() {
+            subscriber.set_subscribe(b"")?;
+        }
+        for &addr in &conf.rxfilter {
+            subscriber.set_subscribe(&[addr])?;
+        }
+        subscriber.connect(&conf.subscribe_endpoint)?;
+
+        Ok(csp_if_zmqhub_t {
+            conf,
+            publisher: Mutex::new(publisher),
+            subscriber: Mutex::new(subscriber),
+            pool: node.buffer_pool().clone(),
+            stats: csp_iface_stats_t::default(),
+        })
+    }
+
+    pub fn conf(&self) -> &csp_if_zmqhub_conf_t {
+        &self.conf
+    }
+
+    fn send_message(&self, packet: &csp_packet_t, via: u16) -> Result<(), CspError> {
+        let dest = if via != CSP_NO_VIA_ADDRESS { via } else { packet.id.dst };
+        let mut message = Vec::with_capacity(CSP_ZMQHUB_OVERHEAD + packet.length());
+        message.push(dest as u8);
+        message.extend_from_slice(&packet.to_bytes(csp_version_t::CSP_VERSION_1)?);
+        self.publisher.lock().unwrap().send(message, 0)?;
+        Ok(())
+    }
+}
+
+impl CspInterface for csp_if_zmqhub_t {
+    fn name(&self) -> &str {
+        &self.conf.name
+    }
+
+    fn mtu(&self) -> usize {
+        self.conf.mtu
+    }
+
+    /// Publish `packet` with `via`, or the packet's destination when `via` is
+    /// CSP_NO_VIA_ADDRESS, as the prefix byte.
+    fn transmit(&self, packet: &csp_packet_t, via: u16) -> Result<(), CspError> {
+        match self.send_message(packet, via) {
+            Ok(()) => {
+                self.stats.count_tx(packet.length());
+                Ok(())
+            }
+            Err(e) => {
+                csp_iface_stats_t::add(&self.stats.tx_error, 1);
+                Err(e)
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for the next message carrying a packet.
+    /// Messages too short for the prefix and header, or longer than the MTU, are
+    /// dropped, and so are messages that find no free buffer.
+    fn receive(&self, timeout: Duration) -> Result<csp_packet_t, CspError> {
+        let subscriber = self.subscriber.lock().unwrap();
+        subscriber.set_rcvtimeo(csp_zmqhub_timeout(timeout))?;
+        loop {
+            let message = subscriber.recv_bytes(0)?;
+            if message.len() < CSP_ZMQHUB_OVERHEAD {
+                csp_iface_stats_t::add(&self.stats.frame, 1);
+                continue;
+            }
+            if message.len() - CSP_ZMQHUB_OVERHEAD > self.conf.mtu {
+                csp_iface_stats_t::add(&self.stats.rx_error, 1);
+                continue;
+            }
+            match self.pool.get_bytes(csp_version_t::CSP_VERSION_1, &message[1..]) {
+                Ok(packet) => {
+                    self.stats.count_rx(packet.length());
+                    return Ok(packet);
+                }
+                Err(CspError::NoBuffers) | Err(CspError::TooLarge { .. }) => csp_iface_stats_t::add(&self.stats.drop, 1),
+                Err(_) => csp_iface_stats_t::add(&self.stats.frame, 1),
+            }
+        }
+    }
+
+    fn stats(&self) -> &csp_iface_stats_t {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    // a local zmqproxy: XSUB and XPUB on ports the system picks, running until
+    // the test process exits. Returns the endpoints nodes publish and
+    // subscribe to.
+    fn csp_zmqproxy_start() -> (String, String) {
+        let context = zmq::Context::new();
+        let bind = |kind| {
+            let socket = context.socket(kind).unwrap();
+            socket.bind("tcp://127.0.0.1:*").unwrap();
+            let endpoint = socket.get_last_endpoint().unwrap().unwrap();
+            (socket, endpoint)
+        };
+        let (xsub, publish_endpoint) = bind(zmq::XSUB);
+        let (xpub, subscribe_endpoint) = bind(zmq::XPUB);
+        thread::spawn(move || {
+            let _ = zmq::proxy(&xsub, &xpub);
+        });
+        (publish_endpoint, subscribe_endpoint)
+    }
+
+    #[test]
+    fn zmqproxy() {
+        let (publish_endpoint, subscribe_endpoint) = csp_zmqproxy_start();
+        let conf = |addr| csp_if_zmqhub_conf_t {
+            publish_endpoint: publish_endpoint.clone(),
+            subscribe_endpoint: subscribe_endpoint.clone(),
+            ..csp_if_zmqhub_conf_t::new(addr, "127.0.0.1")
+        };
+        let node = csp_node_t::new(csp_conf_t::default()).unwrap();
+        let a = csp_if_zmqhub_t::open(&node, conf(1)).unwrap();
+        let b = csp_if_zmqhub_t::open(&node, conf(2)).unwrap();
+        let monitor = csp_if_zmqhub_t::open(&node, conf(CSP_NO_VIA_ADDRESS)).unwrap();
+        // subscriptions take a moment to reach the proxy
+        thread::sleep(Duration::from_millis(300));
+        assert!(matches!(b.receive(Duration::from_millis(10)), Err(CspError::Timeout)));
+
+        let id = csp_id_t { src: 1, dst: 2, dport: 10, sport: 20, ..Default::default() };
+        let packet = csp_packet_t::from_slice(id, b"abc");
+        a.transmit(&packet, CSP_NO_VIA_ADDRESS).unwrap();
+        let got = b.receive(Duration::from_secs(2)).unwrap();
+        assert_eq!(got.id, id);
+        assert_eq!(got.data(), b"abc");
+        assert_eq!(monitor.receive(Duration::from_secs(2)).unwrap().data(), b"abc");
+
+        // through node 5: only the monitor sees it
+        a.transmit(&packet, 5).unwrap();
+        assert_eq!(monitor.receive(Duration::from_secs(2)).unwrap().id, id);
+        assert!(matches!(b.receive(Duration::from_millis(100)), Err(CspError::Timeout)));
+        assert!(matches!(a.receive(Duration::from_millis(10)), Err(CspError::Timeout)));
+        node.stop();
+    }
+}
@@ -0,0 +1,57 @@
+// Interface list
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/csp_iflist.c
+//
+// The interfaces a node knows by name, for route strings and statistics.
+
+use std::sync::Arc;
+
+use super::*;
+
+/// Named interfaces, like libcsp's interface list
+#[derive(Default)]
+pub struct csp_iflist_t {
+    ifaces: Vec<Arc<dyn CspInterface>>,
+}
+
+impl csp_iflist_t {
+    pub fn new() -> csp_iflist_t {
+        csp_iflist_t::default()
+    }
+
+    /// Add an interface, like csp_iflist_add(). Adding the same interface
+    /// twice is a no-op and returns false; names are not checked for uniqueness.
+    pub fn add(&mut self, iface: Arc<dyn CspInterface>) -> bool {
+        if self.ifaces.iter().any(|i| csp_iface_same(i, &iface)) {
+            return false;
+        }
+        self.ifaces.push(iface);
+        true
+    }
+
+    /// First interface called `name`, ignoring case like csp_iflist_get_by_name()
+    pub fn get_by_name(&self, name: &str) -> Option<&Arc<dyn CspInterface>> {
+        self.ifaces.iter().find(|i| i.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Arc<dyn CspInterface>> {
+        self.ifaces.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ifaces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ifaces.is_empty()
+    }
+}
+
+impl fmt::Display for csp_iflist_t {
+    // one line per interface, like csp_iflist_print()
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for iface in &self.ifaces {
+            writeln!(f, "{:<10} {}", iface.name(), iface.stats())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+// Network interfaces
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/include/csp/csp_interface.h
+//
+// Every link, built in or not, is a CspInterface. The router only ever talks
+// to the trait: it hands packets to transmit() and pulls received ones out of
+// receive(), so a new transport is a new type implementing it, nothing more.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::*;
+
+/// Per-interface counters, like the ones in libcsp's csp_iface_t.
+///
+/// Atomic so the interface's own threads and the router can bump them
+/// through a shared reference.
+#[derive(Debug, Default)]
+pub struct csp_iface_stats_t {
+    pub tx: AtomicU32,       // !< Successfully transmitted packets
+    pub rx: AtomicU32,       // !< Successfully received packets
+    pub tx_error: AtomicU32, // !< Transmit errors
+    pub rx_error: AtomicU32, // !< Receive errors, e.g. CRC32 mismatch on the link
+    pub drop: AtomicU32,     // !< Packets dropped: lost fragments, timeouts, full queues
+    pub autherr: AtomicU32,  // !< Packets failing the security check
+    pub frame: AtomicU32,    // !< Malformed frames
+    pub txbytes: AtomicU32,  // !< Payload bytes transmitted
+    pub rxbytes: AtomicU32,  // !< Payload bytes received
+}
+
+impl csp_iface_stats_t {
+    /// Add `n` to one of the counters
+    pub fn add(counter: &AtomicU32, n: u32) {
+        if n > 0 {
+            counter.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    /// Count one packet of `bytes` payload bytes sent
+    pub fn count_tx(&self, bytes: usize) {
+        csp_iface_stats_t::add(&self.tx, 1);
+        csp_iface_stats_t::add(&self.txbytes, bytes as u32);
+    }
+
+    /// Count one packet of `bytes` payload bytes received
+    pub fn count_rx(&self, bytes: usize) {
+        csp_iface_stats_t::add(&self.rx, 1);
+        csp_iface_stats_t::add(&self.rxbytes, bytes as u32);
+    }
+}
+
+impl fmt::Display for csp_iface_stats_t {
+    // same layout as libcsp's csp_iflist_print()
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let get = |counter: &AtomicU32| counter.load(Ordering::Relaxed);
+        write!(
+            f,
+            "tx: {:05} rx: {:05} txe: {:05} rxe: {:05} drop: {:05} autherr: {:05} frame: {:05} txb: {} rxb: {}",
+            get(&self.tx),
+            get(&self.rx),
+            get(&self.tx_error),
+            get(&self.rx_error),
+            get(&self.drop),
+            get(&self.autherr),
+            get(&self.frame),
+            get(&self.txbytes),
+            get(&self.rxbytes),
+        )
+    }
+}
+
+/// A link the router can send packets over and receive packets from.
+///
+/// Implementations are shared between threads, so both hooks take `&self`.
+/// They count their own tx/rx traffic and link errors in `stats()`; the router
+/// adds drops and security failures for packets it rejects.
+pub trait CspInterface: Send + Sync {
+    /// Name used in routing tables and statistics, e.g. "CAN" or "KISS"
+    fn name(&self) -> &str;
+
+    /// Largest payload the link carries
+    fn mtu(&self) -> usize;
+
+    /// Transmit hook, like libcsp's nexthop: send `packet` to `via`, the next
+    /// hop on this link, or to the packet's destination when `via` is
+    /// CSP_NO_VIA_ADDRESS.
+    fn transmit(&self, packet: &csp_packet_t, via: u16) -> Result<(), CspError>;
+
+    /// Receive hook: wait up to `timeout` for the next packet from the link.
+    /// Fails with Timeout when nothing arrives.
+    fn receive(&self, timeout: Duration) -> Result<csp_packet_t, CspError>;
+
+    fn stats(&self) -> &csp_iface_stats_t;
+}
+
+/// True if `a` and `b` are the same interface. Compares the data pointers
+/// only, as vtable pointers for the same type can differ between crates.
+pub fn csp_iface_same(a: &Arc<dyn CspInterface>, b: &Arc<dyn CspInterface>) -> bool {
+    Arc::as_ptr(a) as *const u8 == Arc::as_ptr(b) as *const u8
+}
+
+impl fmt::Debug for dyn CspInterface {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CspInterface").field("name", &self.name()).field("mtu", &self.mtu()).finish()
+    }
+}
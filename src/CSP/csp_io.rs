@@ -0,0 +1,156 @@
+// Outgoing packet processing
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/csp_io.c
+
+use super::*;
+
+/// Keys for the CSP_FHMAC and CSP_FXTEA trailers, derived from the node's
+/// settings like libcsp's csp_*_set_key() do. Without a key, packets needing
+/// it fail.
+#[derive(Clone, Copy, Default)]
+pub struct csp_security_keys_t {
+    pub hmac: Option<[u8; CSP_SHA1_DIGESTSIZE]>, // !< From csp_hmac_key()
+    pub xtea: Option<[u32; 4]>,                  // !< From csp_xtea_key()
+}
+
+impl csp_security_keys_t {
+    pub fn new(conf: &csp_conf_t) -> csp_security_keys_t {
+        csp_security_keys_t {
+            hmac: conf.hmac_key.as_deref().map(csp_hmac_key),
+            xtea: conf.xtea_key.as_deref().map(csp_xtea_key),
+        }
+    }
+}
+
+// connection option pairs (enable, disable) and the header flag they control
+const CSP_SECURITY_OPTS: [(u32, u32, u32); 3] = [
+    (CSP_O_HMAC, CSP_O_NOHMAC, CSP_FHMAC),
+    (CSP_O_CRC32, CSP_O_NOCRC32, CSP_FCRC32),
+    (CSP_O_XTEA, CSP_O_NOXTEA, CSP_FXTEA),
+];
+
+/// Turn connection options (CSP_O_*) into header flags and add the trailers
+/// those flags call for. Run on every packet just before it is handed to an
+/// interface, like the tail of csp_send_direct().
+pub fn csp_send_security(keys: &csp_security_keys_t, opts: u32, packet: &mut csp_packet_t) -> Result<(), CspError> {
+    let mut flags = packet.id.flags as u32;
+    for &(enable, disable, flag) in CSP_SECURITY_OPTS.iter() {
+        if opts & enable != 0 {
+            flags |= flag;
+        }
+        if opts & disable != 0 {
+            flags &= !flag;
+        }
+    }
+    packet.id.flags = flags as u8;
+
+    // same order as libcsp, csp_route_security_check() undoes them in reverse
+    if flags & CSP_FHMAC != 0 {
+        let key = keys.hmac.ok_or(CspError::HmacMismatch)?;
+        csp_hmac_append(&key, packet, false)?;
+    }
+    if flags & CSP_FCRC32 != 0 {
+        csp_crc32_append(packet)?;
+    }
+    if flags & CSP_FXTEA != 0 {
+        let key = keys.xtea.ok_or(CspError::DecryptFailed)?;
+        csp_xtea_encrypt_packet(&key, packet)?;
+    }
+    Ok(())
+}
+
+impl csp_node_t {
+    /// Send `packet` to port `dport` on node `dst` without a connection, like
+    /// csp_sendto(). `opts` are CSP_O_* flags for this packet only; RDP needs
+    /// a connection.
+    pub fn sendto(&self, prio: csp_prio_t, dst: u16, dport: u8, src_port: u8, opts: u32, mut packet: csp_packet_t) -> Result<(), CspError> {
+        if opts & CSP_O_RDP != 0 {
+            return Err(CspError::NotSupported(CSP_O_RDP));
+        }
+        packet.id = csp_id_t::new(self.version(), prio, self.address(), dst, dport, src_port, 0)?;
+        csp_send_security(&self.inner.keys, opts, &mut packet)?;
+        self.send_direct(&packet)
+    }
+
+    /// Answer `request`, a packet from recvfrom(), at the port it came from,
+    /// like csp_sendto_reply()
+    pub fn sendto_reply(&self, request: &csp_packet_t, reply: csp_packet_t, opts: u32) -> Result<(), CspError> {
+        let id = request.id;
+        self.sendto(id.pri, id.src, id.sport, id.dport, opts, reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::CSP::csp_prio_t::*;
+
+    const WAIT: Duration = Duration::from_secs(1);
+    const NOTHING: Duration = Duration::from_millis(100);
+
+    fn node() -> csp_node_t {
+        let node = csp_node_t::new(csp_conf_t { address: 4, ..Default::default() }).unwrap();
+        node.route_start_task().unwrap();
+        node
+    }
+
+    #[test]
+    fn sendto_recvfrom() {
+        let node = node();
+        let (server, client) = (node.socket(CSP_SO_CONN_LESS).unwrap(), node.socket(CSP_SO_CONN_LESS).unwrap());
+        server.bind(15).unwrap();
+        client.bind(16).unwrap();
+
+        node.sendto(CSP_PRIO_HIGH, 4, 15, 16, CSP_O_CRC32, csp_packet_t::from_slice(csp_id_t::default(), b"ping")).unwrap();
+        let request = server.recvfrom(WAIT).unwrap();
+        assert_eq!(request.data(), b"ping");
+        let expected = csp_id_t { pri: CSP_PRIO_HIGH, src: 4, dst: 4, dport: 15, sport: 16, flags: CSP_FCRC32 as u8 };
+        assert_eq!(request.id, expected);
+
+        node.sendto_reply(&request, csp_packet_t::from_slice(csp_id_t::default(), b"pong"), CSP_O_NONE).unwrap();
+        let reply = client.recvfrom(WAIT).unwrap();
+        assert_eq!(reply.data(), b"pong");
+        assert_eq!(reply.id, csp_id_t { dport: 16, sport: 15, flags: 0, ..expected });
+        assert!(matches!(server.recvfrom(NOTHING), Err(CspError::Timeout)));
+        node.stop();
+    }
+
+    #[test]
+    fn sendto_rdp() {
+        let node = node();
+        let socket = node.socket(CSP_SO_CONN_LESS).unwrap();
+        socket.bind(15).unwrap();
+        let packet = csp_packet_t::from_slice(csp_id_t::default(), b"ping");
+        assert!(matches!(node.sendto(CSP_PRIO_NORM, 4, 15, 16, CSP_O_RDP, packet), Err(CspError::NotSupported(CSP_O_RDP))));
+        assert!(matches!(socket.recvfrom(NOTHING), Err(CspError::Timeout)));
+        node.stop();
+    }
+
+    #[test]
+    fn conn_less_socket() {
+        let node = node();
+        let conn_less = node.socket(CSP_SO_CONN_LESS).unwrap();
+        conn_less.bind(15).unwrap();
+        assert!(matches!(conn_less.listen(5), Err(CspError::NotSupported(CSP_SO_CONN_LESS))));
+        assert!(matches!(conn_less.accept(Duration::ZERO), Err(CspError::NotSupported(CSP_SO_CONN_LESS))));
+
+        // a connection-oriented socket on another port works alongside it
+        let server = node.socket(CSP_SO_NONE).unwrap();
+        server.bind(10).unwrap();
+        server.listen(5).unwrap();
+        assert!(matches!(server.recvfrom(Duration::ZERO), Err(CspError::NotSupported(CSP_SO_CONN_LESS))));
+
+        let client = node.connect(CSP_PRIO_NORM, 4, 10, Duration::ZERO, CSP_O_NONE).unwrap();
+        client.send(csp_packet_t::from_slice(client.idout(), b"conn")).unwrap();
+        node.sendto(CSP_PRIO_NORM, 4, 15, 16, CSP_O_NONE, csp_packet_t::from_slice(csp_id_t::default(), b"datagram")).unwrap();
+
+        let conn = server.accept(WAIT).unwrap();
+        assert_eq!(conn.read(WAIT).unwrap().data(), b"conn");
+        assert_eq!(conn_less.recvfrom(WAIT).unwrap().data(), b"datagram");
+        conn.send(csp_packet_t::from_slice(conn.idout(), b"reply")).unwrap();
+        assert_eq!(client.read(WAIT).unwrap().data(), b"reply");
+        assert!(matches!(conn_less.recvfrom(NOTHING), Err(CspError::Timeout)));
+        node.stop();
+    }
+}
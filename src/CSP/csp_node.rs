@@ -0,0 +1,243 @@
+// Node: configuration, interfaces and routing table in one place
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/csp_init.c
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/csp_qfifo.c
+//
+// libcsp keeps this in globals set up by csp_init(); here it is a csp_node_t,
+// a cheap to clone handle, so several nodes can live in one process.
+//
+// Packets the node receives or sends on its own (RDP acks, resends) take
+// their buffers from the node's pool, see csp_buffer.rs; interfaces are
+// opened for a node so they can do the same.
+//
+// Every interface added to a node gets a receive thread that moves packets
+// from the interface into the node's input queue (libcsp's qfifo). The router
+// (csp_route.rs) takes them from there. A loopback interface and a route for
+// the node's own address through it are set up on creation, so packets a node
+// sends to itself go through the same path as everything else.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread;
+use std::time::Duration;
+
+use super::*;
+
+// how long receive threads wait on their interface before checking for stop()
+pub(crate) const CSP_NODE_POLL: Duration = Duration::from_millis(100);
+
+/// Node settings, like libcsp's csp_conf_t
+#[derive(Debug, Clone)]
+pub struct csp_conf_t {
+    pub address: u16,              // !< Own address
+    pub version: csp_version_t,    // !< Header version, sets the address range
+    pub fifo_length: usize,        // !< Packets waiting for the router before interfaces start dropping
+    pub conn_max: usize,           // !< Open connections at most
+    pub conn_queue_length: usize,  // !< Packets waiting on a connection before new ones are dropped
+    pub port_max_bind: u8,         // !< Highest port for services, ports above it are handed out to clients
+    pub conn_dfl_so: u32,          // !< Options (CSP_O_*) added to every connection
+    pub rdp_opt: csp_rdp_opt_t,    // !< RDP settings for connections this node opens
+    pub buffers: usize,            // !< Packet buffers in the node's pool
+    pub buffer_data_size: usize,   // !< Payload bytes per buffer
+    pub hmac_key: Option<Vec<u8>>, // !< Key shared with the nodes we talk HMAC to, like csp_hmac_set_key()
+    pub xtea_key: Option<Vec<u8>>, // !< Key shared with the nodes we talk XTEA to, like csp_xtea_set_key()
+}
+
+impl Default for csp_conf_t {
+    fn default() -> csp_conf_t {
+        csp_conf_t {
+            address: 1,
+            version: csp_version_t::default(),
+            fifo_length: 25,
+            conn_max: 10,
+            conn_queue_length: 10,
+            port_max_bind: 24,
+            conn_dfl_so: CSP_O_NONE,
+            rdp_opt: csp_rdp_opt_t::default(),
+            buffers: 100,
+            buffer_data_size: CSP_BUFFER_SIZE,
+            hmac_key: None,
+            xtea_key: None,
+        }
+    }
+}
+
+/// A received packet waiting for the router, with the interface it came in on
+pub struct csp_qfifo_t {
+    pub packet: csp_packet_t,
+    pub iface: Arc<dyn CspInterface>,
+}
+
+pub(crate) struct csp_node_inner {
+    pub(crate) conf: csp_conf_t,
+    pub(crate) pool: csp_buffer_pool_t,
+    pub(crate) keys: csp_security_keys_t,
+    pub(crate) iflist: RwLock<csp_iflist_t>,
+    pub(crate) rtable: RwLock<csp_rtable_t>,
+    pub(crate) qfifo_tx: mpsc::SyncSender<csp_qfifo_t>,
+    pub(crate) qfifo_rx: Mutex<mpsc::Receiver<csp_qfifo_t>>,
+    pub(crate) conns: Mutex<csp_conn_table_t>,
+    pub(crate) ports: Mutex<csp_port_table_t>,
+    pub(crate) running: AtomicBool,
+    pub(crate) threads: Mutex<Vec<thread::JoinHandle<()>>>,
+}
+
+/// Handle to a CSP node. Clones share the same node.
+///
+/// The node's threads hold a handle too, so a node lives until stop() is
+/// called, even when every handle the application had is gone.
+#[derive(Clone)]
+pub struct csp_node_t {
+    pub(crate) inner: Arc<csp_node_inner>,
+}
+
+impl csp_node_t {
+    /// Create a node with a loopback interface and a route to itself through it
+    pub fn new(conf: csp_conf_t) -> Result<csp_node_t, CspError> {
+        if conf.address > conf.version.host_max() {
+            return Err(CspError::InvalidAddress(conf.address));
+        }
+        if conf.port_max_bind as u32 >= CSP_ID_PORT_MAX {
+            return Err(CspError::InvalidPort(conf.port_max_bind));
+        }
+        let (qfifo_tx, qfifo_rx) = mpsc::sync_channel(conf.fifo_length);
+        let node = csp_node_t {
+            inner: Arc::new(csp_node_inner {
+                iflist: RwLock::new(csp_iflist_t::new()),
+                rtable: RwLock::new(csp_rtable_t::new(conf.version)),
+                qfifo_tx,
+                qfifo_rx: Mutex::new(qfifo_rx),
+                conns: Mutex::new(csp_conn_table_t::new(&conf)),
+                ports: Mutex::new(csp_port_table_t::new(&conf)),
+                running: AtomicBool::new(true),
+                threads: Mutex::new(Vec::new()),
+                pool: csp_buffer_pool_t::new(conf.buffers, conf.buffer_data_size),
+                keys: csp_security_keys_t::new(&conf),
+                conf,
+            }),
+        };
+        let lo: Arc<dyn CspInterface> = Arc::new(csp_if_lo_t::new(&node));
+        node.add_interface(lo.clone())?;
+        let (address, host_bits) = (node.address(), node.version().host_bits());
+        node.rtable_mut().set(address, host_bits, lo, CSP_NO_VIA_ADDRESS)?;
+        Ok(node)
+    }
+
+    pub fn conf(&self) -> &csp_conf_t {
+        &self.inner.conf
+    }
+
+    pub fn address(&self) -> u16 {
+        self.inner.conf.address
+    }
+
+    pub fn version(&self) -> csp_version_t {
+        self.inner.conf.version
+    }
+
+    /// The node's packet buffers
+    pub fn buffer_pool(&self) -> &csp_buffer_pool_t {
+        &self.inner.pool
+    }
+
+    /// Take a buffer from the node's pool, like csp_buffer_get()
+    pub fn buffer_get(&self, size: usize) -> Result<csp_packet_t, CspError> {
+        self.inner.pool.get(size)
+    }
+
+    /// Add an interface and start its receive thread, like csp_iflist_add().
+    /// Routes can name it from then on. Adding it again does nothing.
+    pub fn add_interface(&self, iface: Arc<dyn CspInterface>) -> Result<(), CspError> {
+        if !self.inner.iflist.write().unwrap().add(iface.clone()) {
+            return Ok(());
+        }
+        let node = self.clone();
+        let thread = thread::Builder::new()
+            .name(format!("csp_rx {}", iface.name()))
+            .spawn(move || csp_qfifo_rx_task(node, iface))?;
+        self.inner.threads.lock().unwrap().push(thread);
+        Ok(())
+    }
+
+    pub fn iflist(&self) -> RwLockReadGuard<'_, csp_iflist_t> {
+        self.inner.iflist.read().unwrap()
+    }
+
+    pub fn rtable(&self) -> RwLockReadGuard<'_, csp_rtable_t> {
+        self.inner.rtable.read().unwrap()
+    }
+
+    pub fn rtable_mut(&self) -> RwLockWriteGuard<'_, csp_rtable_t> {
+        self.inner.rtable.write().unwrap()
+    }
+
+    /// Add the routes of a libcsp route string, naming this node's interfaces
+    pub fn rtable_load(&self, rtable: &str) -> Result<usize, CspError> {
+        let iflist = self.iflist();
+        self.rtable_mut().load(rtable, &iflist)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.inner.running.load(Ordering::SeqCst)
+    }
+
+    /// Stop and join every thread of the node: receive threads and the router
+    /// task. Interfaces stay open until their last handle is dropped.
+    pub fn stop(&self) {
+        self.inner.running.store(false, Ordering::SeqCst);
+        let threads: Vec<_> = self.inner.threads.lock().unwrap().drain(..).collect();
+        for thread in threads {
+            if thread.thread().id() != thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    /// Queue a received packet for the router, like csp_qfifo_write().
+    /// Drops it, counting the drop on `iface`, when the queue is full.
+    pub fn qfifo_write(&self, packet: csp_packet_t, iface: Arc<dyn CspInterface>) {
+        if let Err(mpsc::TrySendError::Full(entry) | mpsc::TrySendError::Disconnected(entry)) =
+            self.inner.qfifo_tx.try_send(csp_qfifo_t { packet, iface })
+        {
+            csp_iface_stats_t::add(&entry.iface.stats().drop, 1);
+        }
+    }
+
+    /// Next received packet, waiting up to `timeout`, like csp_qfifo_read()
+    pub fn qfifo_read(&self, timeout: Duration) -> Result<csp_qfifo_t, CspError> {
+        self.inner.qfifo_rx.lock().unwrap().recv_timeout(timeout).map_err(|_| CspError::Timeout)
+    }
+}
+
+// receive thread: move packets from one interface into the qfifo until stop()
+// or the interface fails for good
+fn csp_qfifo_rx_task(node: csp_node_t, iface: Arc<dyn CspInterface>) {
+    while node.is_running() {
+        match iface.receive(CSP_NODE_POLL) {
+            Ok(packet) => node.qfifo_write(packet, iface.clone()),
+            Err(CspError::Timeout) => {}
+            Err(CspError::Io(ref e)) if e.kind() == std::io::ErrorKind::BrokenPipe => break,
+            // a link error; count it and do not spin on a link that keeps failing
+            Err(_) => {
+                csp_iface_stats_t::add(&iface.stats().rx_error, 1);
+                thread::sleep(CSP_NODE_POLL);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_interface_twice() {
+        let node = csp_node_t::new(csp_conf_t::default()).unwrap();
+        let lo = node.iflist().get_by_name(CSP_IF_LOOPBACK_NAME).unwrap().clone();
+        let threads = node.inner.threads.lock().unwrap().len();
+        node.add_interface(lo).unwrap();
+        assert_eq!(node.iflist().len(), 1);
+        assert_eq!(node.inner.threads.lock().unwrap().len(), threads);
+        node.stop();
+    }
+}
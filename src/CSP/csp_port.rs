@@ -0,0 +1,407 @@
+// Ports and server sockets
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/csp_port.c
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/csp_io.c (csp_socket, csp_accept)
+//
+// A server binds a socket to one or more ports, or to CSP_ANY for every port
+// up to port_max_bind nothing else is bound to, then listens on it. The first
+// packet for a bound port that matches no open connection opens a new one,
+// which waits in the socket's queue until accept() takes it. The socket's
+// CSP_SO_* options become the connection's, so they are checked on every
+// packet it receives. An RDP connection reaches the queue once its handshake
+// is done, see csp_rdp.rs.
+//
+// A CSP_SO_CONN_LESS socket opens no connections. Packets for its ports are
+// checked against its options and queued as they are, header included, for
+// recvfrom(); replies go out with csp_node_t::sendto().
+
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::*;
+
+// options csp_socket() accepts
+const CSP_SO_SUPPORTED: u32 = CSP_SO_RDPREQ
+    | CSP_SO_RDPPROHIB
+    | CSP_SO_HMACREQ
+    | CSP_SO_HMACPROHIB
+    | CSP_SO_XTEAREQ
+    | CSP_SO_XTEAPROHIB
+    | CSP_SO_CRC32REQ
+    | CSP_SO_CRC32PROHIB
+    | CSP_SO_CONN_LESS;
+
+// new connections waiting for accept()
+type csp_conn_queue_t = mpsc::Receiver<Arc<csp_conn_inner>>;
+
+pub(crate) struct csp_socket_inner {
+    pub(crate) opts: u32,
+    queue_tx: Mutex<Option<mpsc::SyncSender<Arc<csp_conn_inner>>>>,
+    // shared so accept() can wait without holding the lock
+    queue_rx: Mutex<Option<Arc<Mutex<csp_conn_queue_t>>>>,
+    // packets of a connection-less socket
+    packet_tx: Option<mpsc::SyncSender<csp_packet_t>>,
+    packet_rx: Option<Mutex<mpsc::Receiver<csp_packet_t>>>,
+}
+
+impl csp_socket_inner {
+    /// Queue a new connection for accept(), false if the socket is not
+    /// listening or its backlog is full
+    pub(crate) fn enqueue(&self, conn: Arc<csp_conn_inner>) -> bool {
+        let queue_tx = self.queue_tx.lock().unwrap();
+        queue_tx.as_ref().is_some_and(|tx| tx.try_send(conn).is_ok())
+    }
+}
+
+/// Sockets bound to the node's ports, like libcsp's port table
+pub(crate) struct csp_port_table_t {
+    port_max_bind: u8,
+    ports: HashMap<u8, Arc<csp_socket_inner>>, // keyed on port or CSP_ANY
+}
+
+impl csp_port_table_t {
+    pub(crate) fn new(conf: &csp_conf_t) -> csp_port_table_t {
+        csp_port_table_t {
+            port_max_bind: conf.port_max_bind,
+            ports: HashMap::new(),
+        }
+    }
+
+    /// Socket for packets to `dport`, falling back to the CSP_ANY one.
+    /// Like csp_port_get_socket(), ports above port_max_bind have none.
+    pub(crate) fn get_socket(&self, dport: u8) -> Option<Arc<csp_socket_inner>> {
+        if dport > self.port_max_bind {
+            return None;
+        }
+        self.ports.get(&dport).or_else(|| self.ports.get(&CSP_ANY)).cloned()
+    }
+
+    fn bind(&mut self, port: u8, socket: &Arc<csp_socket_inner>) -> Result<(), CspError> {
+        if port > self.port_max_bind && port != CSP_ANY {
+            return Err(CspError::InvalidPort(port));
+        }
+        if self.ports.contains_key(&port) {
+            return Err(CspError::PortInUse(port));
+        }
+        self.ports.insert(port, socket.clone());
+        Ok(())
+    }
+
+    // free every port bound to `socket`
+    fn unbind(&mut self, socket: &Arc<csp_socket_inner>) {
+        self.ports.retain(|_, s| !Arc::ptr_eq(s, socket));
+    }
+}
+
+/// A server socket, like libcsp's csp_socket_t. Dropping it frees its ports
+/// and closes the connections still waiting to be accepted.
+/// With CSP_SO_CONN_LESS it receives packets instead of connections.
+pub struct csp_socket_t {
+    node: csp_node_t,
+    inner: Arc<csp_socket_inner>,
+}
+
+impl csp_node_t {
+    /// Create a socket with CSP_SO_* `opts`, like csp_socket(). A
+    /// connection-less socket queues up to conn_queue_length packets.
+    pub fn socket(&self, opts: u32) -> Result<csp_socket_t, CspError> {
+        if opts & !CSP_SO_SUPPORTED != 0 {
+            return Err(CspError::NotSupported(opts & !CSP_SO_SUPPORTED));
+        }
+        let (packet_tx, packet_rx) = if opts & CSP_SO_CONN_LESS != 0 {
+            let (tx, rx) = mpsc::sync_channel(self.conf().conn_queue_length);
+            (Some(tx), Some(Mutex::new(rx)))
+        } else {
+            (None, None)
+        };
+        Ok(csp_socket_t {
+            node: self.clone(),
+            inner: Arc::new(csp_socket_inner {
+                opts,
+                queue_tx: Mutex::new(None),
+                queue_rx: Mutex::new(None),
+                packet_tx,
+                packet_rx,
+            }),
+        })
+    }
+
+    // first packet of a connection to one of our ports: open the connection
+    // and queue it for accept(), like the socket branch of csp_route_work().
+    // Connection-less sockets get the packet itself.
+    pub(crate) fn deliver_new(&self, mut packet: csp_packet_t, iface: Arc<dyn CspInterface>) {
+        let socket = self.inner.ports.lock().unwrap().get_socket(packet.id.dport);
+        let socket = match socket {
+            Some(socket) => socket,
+            None => return csp_iface_stats_t::add(&iface.stats().drop, 1),
+        };
+        let idin = packet.id;
+        if csp_route_security_check(&self.inner.keys, socket.opts, &mut packet).is_err() {
+            return csp_iface_stats_t::add(&iface.stats().autherr, 1);
+        }
+        if let Some(packet_tx) = &socket.packet_tx {
+            if packet_tx.try_send(packet).is_err() {
+                csp_iface_stats_t::add(&iface.stats().drop, 1);
+            }
+            return;
+        }
+
+        // replies mirror the first packet, flags included
+        let idout = csp_id_t {
+            src: idin.dst,
+            dst: idin.src,
+            dport: idin.sport,
+            sport: idin.dport,
+            ..idin
+        };
+        let conn = self.inner.conns.lock().unwrap().new_conn(idin, idout, socket.opts);
+        let conn = match conn {
+            Ok(conn) => conn,
+            Err(_) => return csp_iface_stats_t::add(&iface.stats().drop, 1),
+        };
+        if let Some(rdp) = &conn.rdp {
+            rdp.lock().unwrap().socket = Some(socket);
+            return csp_rdp_new_packet(self, &conn, packet);
+        }
+        // a socket that is not listening or has a full backlog refuses it,
+        // closing the connection again
+        if conn.enqueue(packet).is_err() || !socket.enqueue(conn.clone()) {
+            self.inner.conns.lock().unwrap().remove(&conn);
+            csp_iface_stats_t::add(&iface.stats().drop, 1);
+        }
+    }
+}
+
+impl csp_socket_t {
+    /// Bind the socket to `port`, or to CSP_ANY for every port without a
+    /// socket of its own, like csp_bind(). A socket can be bound to several ports.
+    pub fn bind(&self, port: u8) -> Result<(), CspError> {
+        self.node.inner.ports.lock().unwrap().bind(port, &self.inner)
+    }
+
+    /// Accept up to `backlog` new connections waiting for accept(), like
+    /// csp_listen(). Listening again replaces the queue, closing what is in it.
+    /// Connection-less sockets need no listen().
+    pub fn listen(&self, backlog: usize) -> Result<(), CspError> {
+        if self.is_conn_less() {
+            return Err(CspError::NotSupported(CSP_SO_CONN_LESS));
+        }
+        let (tx, rx) = mpsc::sync_channel(backlog);
+        // dropping the old sender wakes an accept() waiting on the old queue
+        *self.inner.queue_tx.lock().unwrap() = Some(tx);
+        let old = self.inner.queue_rx.lock().unwrap().replace(Arc::new(Mutex::new(rx)));
+        if let Some(old) = old {
+            self.close_queued(&old.lock().unwrap());
+        }
+        Ok(())
+    }
+
+    /// Next new connection, waiting up to `timeout`, like csp_accept().
+    /// Fails with Timeout straight away if the socket is not listening, and
+    /// as soon as listen() replaces the queue it waits on.
+    pub fn accept(&self, timeout: Duration) -> Result<csp_conn_t, CspError> {
+        if self.is_conn_less() {
+            return Err(CspError::NotSupported(CSP_SO_CONN_LESS));
+        }
+        let queue_rx = self.inner.queue_rx.lock().unwrap().clone().ok_or(CspError::Timeout)?;
+        let inner = queue_rx.lock().unwrap().recv_timeout(timeout).map_err(|_| CspError::Timeout)?;
+        Ok(csp_conn_t { node: self.node.clone(), inner })
+    }
+
+    /// Next packet on a connection-less socket, header included, waiting up
+    /// to `timeout`, like csp_recvfrom(). Trailers the socket options checked
+    /// are stripped, the flags announcing them are kept.
+    pub fn recvfrom(&self, timeout: Duration) -> Result<csp_packet_t, CspError> {
+        match &self.inner.packet_rx {
+            Some(rx) => rx.lock().unwrap().recv_timeout(timeout).map_err(|_| CspError::Timeout),
+            None => Err(CspError::NotSupported(CSP_SO_CONN_LESS)),
+        }
+    }
+
+    pub fn opts(&self) -> u32 {
+        self.inner.opts
+    }
+
+    pub fn is_conn_less(&self) -> bool {
+        self.inner.opts & CSP_SO_CONN_LESS != 0
+    }
+
+    // close connections nobody accepted
+    fn close_queued(&self, queue_rx: &csp_conn_queue_t) {
+        for inner in queue_rx.try_iter() {
+            drop(csp_conn_t { node: self.node.clone(), inner });
+        }
+    }
+}
+
+impl Drop for csp_socket_t {
+    fn drop(&mut self) {
+        self.node.inner.ports.lock().unwrap().unbind(&self.inner);
+        self.inner.queue_tx.lock().unwrap().take();
+        if let Some(queue_rx) = self.inner.queue_rx.lock().unwrap().take() {
+            self.close_queued(&queue_rx.lock().unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::CSP::csp_prio_t::*;
+
+    const WAIT: Duration = Duration::from_secs(1);
+    const NOTHING: Duration = Duration::from_millis(100);
+
+    fn node() -> csp_node_t {
+        let node = csp_node_t::new(csp_conf_t::default()).unwrap();
+        node.route_start_task().unwrap();
+        node
+    }
+
+    // open a connection to our own `dport` and send it a first packet
+    fn ping(node: &csp_node_t, dport: u8) -> csp_conn_t {
+        let conn = node.connect(CSP_PRIO_NORM, node.address(), dport, Duration::ZERO, CSP_O_NONE).unwrap();
+        conn.send(csp_packet_t::from_slice(conn.idout(), b"ping")).unwrap();
+        conn
+    }
+
+    fn lo_drops(node: &csp_node_t) -> u32 {
+        node.iflist().get_by_name(CSP_IF_LOOPBACK_NAME).unwrap().stats().drop.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn bind_and_any() {
+        let node = node();
+        let (port, any) = (node.socket(CSP_SO_NONE).unwrap(), node.socket(CSP_SO_NONE).unwrap());
+        port.bind(10).unwrap();
+        port.bind(11).unwrap();
+        any.bind(CSP_ANY).unwrap();
+        port.listen(5).unwrap();
+        any.listen(5).unwrap();
+
+        let clients: Vec<csp_conn_t> = [10, 11, 12, node.conf().port_max_bind].iter().map(|&p| ping(&node, p)).collect();
+        for (client, socket) in clients.iter().zip([&port, &port, &any, &any]) {
+            let conn = socket.accept(WAIT).unwrap();
+            assert_eq!((conn.dport(), conn.sport()), (client.sport(), client.dport()));
+            assert_eq!(conn.read(WAIT).unwrap().data(), b"ping");
+        }
+        assert!(matches!(port.accept(NOTHING), Err(CspError::Timeout)));
+        assert!(matches!(any.accept(NOTHING), Err(CspError::Timeout)));
+        node.stop();
+    }
+
+    #[test]
+    fn port_in_use() {
+        let node = node();
+        let (a, b) = (node.socket(CSP_SO_NONE).unwrap(), node.socket(CSP_SO_NONE).unwrap());
+        a.bind(10).unwrap();
+        a.bind(CSP_ANY).unwrap();
+        assert!(matches!(a.bind(10), Err(CspError::PortInUse(10))));
+        assert!(matches!(b.bind(10), Err(CspError::PortInUse(10))));
+        assert!(matches!(b.bind(CSP_ANY), Err(CspError::PortInUse(CSP_ANY))));
+        b.bind(11).unwrap();
+
+        // dropping a socket frees all its ports
+        drop(a);
+        b.bind(10).unwrap();
+        b.bind(CSP_ANY).unwrap();
+        node.stop();
+    }
+
+    #[test]
+    fn above_port_max_bind() {
+        let node = node();
+        let port_max_bind = node.conf().port_max_bind;
+        let any = node.socket(CSP_SO_NONE).unwrap();
+        assert!(matches!(any.bind(port_max_bind + 1), Err(CspError::InvalidPort(_))));
+        assert!(matches!(any.bind(CSP_ID_PORT_MAX as u8), Err(CspError::InvalidPort(_))));
+        any.bind(CSP_ANY).unwrap();
+        any.listen(5).unwrap();
+
+        // client ports are not served, not even by CSP_ANY
+        let _client = ping(&node, port_max_bind + 10);
+        assert!(matches!(any.accept(NOTHING), Err(CspError::Timeout)));
+        assert_eq!(lo_drops(&node), 1);
+        node.stop();
+    }
+
+    #[test]
+    fn backlog_full() {
+        let node = node();
+        let socket = node.socket(CSP_SO_NONE).unwrap();
+        socket.bind(10).unwrap();
+        socket.listen(1).unwrap();
+
+        let first = ping(&node, 10);
+        let _second = ping(&node, 10);
+        let conn = socket.accept(WAIT).unwrap();
+        assert_eq!(conn.dport(), first.sport());
+        assert!(matches!(socket.accept(NOTHING), Err(CspError::Timeout)));
+        assert_eq!(lo_drops(&node), 1);
+
+        // the refused connection was closed again, the accepted one stays
+        assert_eq!(node.inner.conns.lock().unwrap().list().len(), 3);
+        node.stop();
+    }
+
+    #[test]
+    fn accept_not_listening() {
+        let node = node();
+        let socket = node.socket(CSP_SO_NONE).unwrap();
+        socket.bind(10).unwrap();
+        let start = Instant::now();
+        assert!(matches!(socket.accept(WAIT), Err(CspError::Timeout)));
+        assert!(start.elapsed() < WAIT);
+
+        // nobody takes the connection
+        let _client = ping(&node, 10);
+        thread::sleep(NOTHING);
+        assert_eq!(lo_drops(&node), 1);
+        node.stop();
+    }
+
+    #[test]
+    fn listen_again() {
+        let node = node();
+        let socket = node.socket(CSP_SO_NONE).unwrap();
+        socket.bind(10).unwrap();
+        socket.listen(5).unwrap();
+        let _clients = (ping(&node, 10), ping(&node, 10));
+        thread::sleep(NOTHING);
+        assert_eq!(node.inner.conns.lock().unwrap().list().len(), 4);
+
+        // the queued connections are closed, new ones still come in
+        socket.listen(5).unwrap();
+        assert_eq!(node.inner.conns.lock().unwrap().list().len(), 2);
+        assert!(matches!(socket.accept(NOTHING), Err(CspError::Timeout)));
+        let client = ping(&node, 10);
+        assert_eq!(socket.accept(WAIT).unwrap().dport(), client.sport());
+        node.stop();
+    }
+
+    #[test]
+    fn accept_does_not_block_listen() {
+        let node = node();
+        let socket = node.socket(CSP_SO_NONE).unwrap();
+        socket.bind(10).unwrap();
+        socket.listen(5).unwrap();
+
+        let start = Instant::now();
+        thread::scope(|scope| {
+            let waiting = scope.spawn(|| socket.accept(Duration::from_secs(10)));
+            thread::sleep(NOTHING);
+            socket.listen(5).unwrap();
+            assert!(matches!(waiting.join().unwrap(), Err(CspError::Timeout)));
+        });
+        assert!(start.elapsed() < WAIT);
+
+        let client = ping(&node, 10);
+        assert_eq!(socket.accept(WAIT).unwrap().dport(), client.sport());
+        node.stop();
+    }
+}
@@ -0,0 +1,1031 @@
+// Reliable Datagram Protocol
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/transport/csp_rdp.c
+//
+// RDP runs on connections whose packets carry CSP_FRDP. Every packet ends in
+// a five byte RDP header: flags (SYN, ACK, EAK, RST), then the sequence and
+// acknowledgement numbers, big endian. It goes on before the security
+// trailers and comes off after them, like in libcsp, so the two interoperate.
+//
+// The client sends SYN with its options, the server takes them over and
+// answers SYN/ACK, and the client's ACK opens the connection on both sides;
+// only then does the server's connection reach accept(). Up to window_size
+// data packets may be unacknowledged. The sender keeps a copy of each until it
+// is acked and sends it again every packet_timeout. The receiver delivers in
+// order, holds packets that arrive early and lists them in an EACK, so the
+// sender drops them from its queue and resends the ones missing before them.
+// Closing sends RST; the connection lingers in CLOSE_WAIT until the peer
+// answers with RST or conn_timeout passes.
+//
+// Timeouts are checked by the router, see csp_node_t::route_work().
+
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::*;
+
+// header flags
+const RDP_SYN: u8 = 0x08;
+const RDP_ACK: u8 = 0x04;
+const RDP_EAK: u8 = 0x02;
+const RDP_RST: u8 = 0x01;
+
+const CSP_RDP_HEADER_SIZE: usize = 5;
+// room for a header and every security trailer, like libcsp's csp_buffer_get(20)
+const CSP_RDP_CMP_SIZE: usize = 20;
+// options carried by SYN: six 32-bit words
+const CSP_RDP_SYN_SIZE: usize = 24;
+// largest window whose two copies stay within half the 16 bit sequence space
+const CSP_RDP_WINDOW_MAX: u32 = 0x3FFF;
+
+/// RDP settings, like csp_rdp_set_opt(). The client sends its own with SYN
+/// and the server uses them for the connection. Acks are only sent while the
+/// connection's queue has room for two windows, so conn_queue_length must be
+/// larger than 2 * window_size; a server cuts a larger window from a SYN down
+/// to fit.
+#[derive(Debug, Clone, Copy)]
+pub struct csp_rdp_opt_t {
+    pub window_size: u32,         // !< Data packets unacknowledged at most
+    pub conn_timeout: Duration,   // !< Time without an ack before the connection is given up
+    pub packet_timeout: Duration, // !< Time before an unacknowledged packet is sent again
+    pub delayed_acks: bool,       // !< Ack every ack_delay_count packets or ack_timeout, not every packet
+    pub ack_timeout: Duration,    // !< Longest an ack is delayed
+    pub ack_delay_count: u32,     // !< Packets received before a delayed ack is due
+}
+
+impl Default for csp_rdp_opt_t {
+    fn default() -> csp_rdp_opt_t {
+        csp_rdp_opt_t {
+            window_size: 4,
+            conn_timeout: Duration::from_millis(10000),
+            packet_timeout: Duration::from_millis(1000),
+            delayed_acks: true,
+            ack_timeout: Duration::from_millis(250),
+            ack_delay_count: 2,
+        }
+    }
+}
+
+/// Connection state, like libcsp's rdp state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum csp_rdp_state_t {
+    RDP_CLOSED,
+    RDP_SYN_SENT,
+    RDP_SYN_RCVD,
+    RDP_OPEN,
+    RDP_CLOSE_WAIT,
+}
+
+use self::csp_rdp_state_t::*;
+
+// a sent packet waiting for its ack, RDP header included, security trailers not
+struct csp_rdp_tx_t {
+    packet: csp_packet_t,
+    first: Instant, // !< First sent, for conn_timeout
+    last: Instant,  // !< Last sent, for packet_timeout
+}
+
+/// RDP state of one connection, like the rdp member of libcsp's csp_conn_t
+pub(crate) struct csp_rdp_t {
+    state: csp_rdp_state_t,
+    opt: csp_rdp_opt_t,
+    snd_iss: u16, // !< Initial sequence number sent
+    snd_nxt: u16, // !< Next sequence number to send
+    snd_una: u16, // !< Oldest sequence number not acknowledged
+    rcv_irs: u16, // !< Initial sequence number received
+    rcv_cur: u16, // !< Last sequence number delivered in order
+    rcv_lsa: u16, // !< Last sequence number acknowledged
+    timestamp: Instant,
+    ack_timestamp: Instant,
+    tx_queue: VecDeque<csp_rdp_tx_t>,
+    rx_queue: Vec<(u16, csp_packet_t)>,
+    // listening socket of a server connection until the handshake hands it over
+    pub(crate) socket: Option<Arc<csp_socket_inner>>,
+    half_open: bool,
+    closed: bool, // the application dropped its handle
+}
+
+impl csp_rdp_t {
+    pub(crate) fn new(opt: csp_rdp_opt_t) -> csp_rdp_t {
+        let now = Instant::now();
+        csp_rdp_t {
+            state: RDP_CLOSED,
+            opt,
+            snd_iss: 0,
+            snd_nxt: 0,
+            snd_una: 0,
+            rcv_irs: 0,
+            rcv_cur: 0,
+            rcv_lsa: 0,
+            timestamp: now,
+            ack_timestamp: now,
+            tx_queue: VecDeque::new(),
+            rx_queue: Vec::new(),
+            socket: None,
+            half_open: false,
+            closed: false,
+        }
+    }
+
+    pub(crate) fn state(&self) -> csp_rdp_state_t {
+        self.state
+    }
+
+    fn window(&self) -> u16 {
+        self.opt.window_size.clamp(1, CSP_RDP_WINDOW_MAX) as u16
+    }
+
+    // new initial sequence number
+    fn reset_iss(&mut self) {
+        self.snd_iss = RandomState::new().build_hasher().finish() as u16;
+        self.snd_nxt = self.snd_iss.wrapping_add(1);
+        self.snd_una = self.snd_iss;
+    }
+}
+
+// sequence numbers wrap, compare them like libcsp
+fn csp_rdp_seq_before(seq: u16, cmp: u16) -> bool {
+    (seq.wrapping_sub(cmp) as i16) < 0
+}
+
+fn csp_rdp_seq_after(seq: u16, cmp: u16) -> bool {
+    csp_rdp_seq_before(cmp, seq)
+}
+
+fn csp_rdp_seq_between(seq: u16, start: u16, end: u16) -> bool {
+    end.wrapping_sub(start) >= seq.wrapping_sub(start)
+}
+
+fn csp_rdp_header_add(packet: &mut csp_packet_t, flags: u8, seq_nr: u16, ack_nr: u16) -> Result<(), CspError> {
+    let seq = seq_nr.to_be_bytes();
+    let ack = ack_nr.to_be_bytes();
+    packet.append(&[flags, seq[0], seq[1], ack[0], ack[1]])
+}
+
+// strip the header, returning (flags, seq_nr, ack_nr)
+fn csp_rdp_header_remove(packet: &mut csp_packet_t) -> Option<(u8, u16, u16)> {
+    let offset = packet.length().checked_sub(CSP_RDP_HEADER_SIZE)?;
+    let header = (packet.read_u8(offset)?, packet.read_u16(offset + 1)?, packet.read_u16(offset + 3)?);
+    packet.set_length(offset).ok()?;
+    Some(header)
+}
+
+// copy into one of the node's buffers, like csp_buffer_clone()
+fn csp_rdp_clone(node: &csp_node_t, packet: &csp_packet_t) -> Result<csp_packet_t, CspError> {
+    node.buffer_pool().get_slice(packet.id, packet.data())
+}
+
+fn csp_rdp_ms(duration: Duration) -> u32 {
+    duration.as_millis().min(u32::MAX as u128) as u32
+}
+
+// control packet, or `packet` with a header added, like csp_rdp_send_cmp()
+fn csp_rdp_send_cmp(node: &csp_node_t, conn: &csp_conn_inner, rdp: &mut csp_rdp_t, packet: Option<csp_packet_t>, flags: u8, seq_nr: u16, ack_nr: u16) -> Result<(), CspError> {
+    let mut packet = match packet {
+        Some(packet) => packet,
+        None => node.buffer_get(CSP_RDP_CMP_SIZE)?,
+    };
+    csp_rdp_header_add(&mut packet, flags, seq_nr, ack_nr)?;
+
+    // SYN and SYN/ACK are sent again until acked, like data
+    if flags & RDP_SYN != 0 {
+        let now = Instant::now();
+        rdp.tx_queue.push_back(csp_rdp_tx_t { packet: csp_rdp_clone(node, &packet)?, first: now, last: now });
+    }
+    node.conn_transmit(conn, packet)?;
+
+    if flags & RDP_ACK != 0 {
+        rdp.rcv_lsa = ack_nr;
+        rdp.ack_timestamp = Instant::now();
+    }
+    Ok(())
+}
+
+// ack the in-order packets and list the early ones, like csp_rdp_send_eack()
+fn csp_rdp_send_eack(node: &csp_node_t, conn: &csp_conn_inner, rdp: &mut csp_rdp_t) -> Result<(), CspError> {
+    let mut packet = node.buffer_get(CSP_RDP_CMP_SIZE + 2 * rdp.rx_queue.len())?;
+    for (seq_nr, _) in &rdp.rx_queue {
+        packet.append(&seq_nr.to_be_bytes())?;
+    }
+    let (seq_nr, ack_nr) = (rdp.snd_nxt, rdp.rcv_cur);
+    csp_rdp_send_cmp(node, conn, rdp, Some(packet), RDP_ACK | RDP_EAK, seq_nr, ack_nr)
+}
+
+fn csp_rdp_send_syn(node: &csp_node_t, conn: &csp_conn_inner, rdp: &mut csp_rdp_t) -> Result<(), CspError> {
+    let opt = rdp.opt;
+    let mut packet = node.buffer_get(CSP_RDP_SYN_SIZE + CSP_RDP_CMP_SIZE)?;
+    for word in &[
+        opt.window_size,
+        csp_rdp_ms(opt.conn_timeout),
+        csp_rdp_ms(opt.packet_timeout),
+        opt.delayed_acks as u32,
+        csp_rdp_ms(opt.ack_timeout),
+        opt.ack_delay_count,
+    ] {
+        packet.append(&word.to_be_bytes())?;
+    }
+    let iss = rdp.snd_iss;
+    csp_rdp_send_cmp(node, conn, rdp, Some(packet), RDP_SYN, iss, 0)
+}
+
+// options from the client's SYN. The window is cut down to what our
+// connection queue can hold twice over, whatever the peer asks for.
+fn csp_rdp_read_syn(node: &csp_node_t, packet: &csp_packet_t, opt: &mut csp_rdp_opt_t) {
+    if packet.length() < CSP_RDP_SYN_SIZE {
+        return;
+    }
+    let queue_max = node.conf().conn_queue_length.saturating_sub(1) / 2;
+    let window_max = (queue_max.min(CSP_RDP_WINDOW_MAX as usize) as u32).max(1);
+    let word = |i: usize| packet.read_u32(4 * i).unwrap_or(0);
+    *opt = csp_rdp_opt_t {
+        window_size: word(0).clamp(1, window_max),
+        conn_timeout: Duration::from_millis(word(1) as u64),
+        packet_timeout: Duration::from_millis(word(2) as u64),
+        delayed_acks: word(3) != 0,
+        ack_timeout: Duration::from_millis(word(4) as u64),
+        ack_delay_count: word(5),
+    };
+}
+
+fn csp_rdp_should_ack(rdp: &csp_rdp_t) -> bool {
+    !rdp.opt.delayed_acks
+        || rdp.ack_timestamp.elapsed() > rdp.opt.ack_timeout
+        || csp_rdp_seq_after(rdp.rcv_cur, rdp.rcv_lsa.wrapping_add(rdp.opt.ack_delay_count as u16))
+}
+
+// ack what was received, if the application keeps up with reading, like csp_rdp_check_ack()
+fn csp_rdp_check_ack(node: &csp_node_t, conn: &csp_conn_inner, rdp: &mut csp_rdp_t) {
+    let free = node.conf().conn_queue_length.saturating_sub(conn.queued());
+    if free > 2 * rdp.window() as usize && csp_rdp_should_ack(rdp) {
+        let (seq_nr, ack_nr) = (rdp.snd_nxt, rdp.rcv_cur);
+        let _ = csp_rdp_send_cmp(node, conn, rdp, None, RDP_ACK, seq_nr, ack_nr);
+    }
+}
+
+// drop what the peer received early, resend what it is missing before that,
+// like csp_rdp_flush_eack()
+fn csp_rdp_flush_eack(rdp: &mut csp_rdp_t, eack: &csp_packet_t) {
+    let acked: Vec<u16> = (0..eack.length() / 2).filter_map(|i| eack.read_u16(2 * i)).collect();
+    let resend = Instant::now().checked_sub(rdp.opt.packet_timeout);
+    rdp.tx_queue.retain_mut(|tx| {
+        let seq_nr = tx.packet.read_u16(tx.packet.length() - 4).unwrap_or(0);
+        if acked.iter().any(|&a| csp_rdp_seq_after(a, seq_nr)) {
+            if let Some(resend) = resend {
+                tx.last = resend;
+            }
+        }
+        !acked.contains(&seq_nr)
+    });
+}
+
+// deliver packets held back that are now in order, like csp_rdp_rx_queue_flush()
+fn csp_rdp_rx_queue_flush(conn: &csp_conn_inner, rdp: &mut csp_rdp_t) {
+    loop {
+        let next = rdp.rcv_cur.wrapping_add(1);
+        rdp.rx_queue.retain(|(seq_nr, _)| !csp_rdp_seq_before(*seq_nr, next));
+        match rdp.rx_queue.iter().position(|(seq_nr, _)| *seq_nr == next) {
+            Some(index) => {
+                let (_, packet) = rdp.rx_queue.remove(index);
+                rdp.rcv_cur = next;
+                let _ = conn.enqueue(packet);
+            }
+            None => break,
+        }
+    }
+}
+
+// the connection is finished: forget it if the application does not hold it,
+// otherwise wake the application, which gets Reset from then on
+fn csp_rdp_release(node: &csp_node_t, conn: &Arc<csp_conn_inner>, rdp: &mut csp_rdp_t) {
+    if rdp.socket.take().is_some() || rdp.closed {
+        node.inner.conns.lock().unwrap().remove(conn);
+    } else {
+        conn.shutdown();
+    }
+    conn.tx_wait.notify_all();
+}
+
+/// Open the connection: send SYN and wait up to `timeout` for SYN/ACK, like
+/// csp_rdp_connect(). Retries once if the peer still has a half-open
+/// connection for the same ports.
+pub(crate) fn csp_rdp_connect(node: &csp_node_t, conn: &Arc<csp_conn_inner>, timeout: Duration) -> Result<(), CspError> {
+    let mut rdp = conn.rdp.as_ref().ok_or(CspError::NotSupported(CSP_O_RDP))?.lock().unwrap();
+    for _ in 0..2 {
+        rdp.reset_iss();
+        rdp.state = RDP_SYN_SENT;
+        rdp.half_open = false;
+        rdp.tx_queue.clear();
+        rdp.rx_queue.clear();
+        csp_rdp_send_syn(node, conn, &mut rdp)?;
+
+        let (guard, _) = conn
+            .tx_wait
+            .wait_timeout_while(rdp, timeout, |rdp| rdp.state == RDP_SYN_SENT && !rdp.half_open)
+            .unwrap();
+        rdp = guard;
+        if rdp.state == RDP_OPEN {
+            return Ok(());
+        }
+        if !rdp.half_open {
+            break;
+        }
+    }
+    rdp.state = RDP_CLOSE_WAIT;
+    Err(CspError::Timeout)
+}
+
+/// Send a data packet, waiting for room in the window, like csp_rdp_send().
+/// Fails with Reset once the connection is no longer open. A packet that
+/// cannot be sent uses up no sequence number.
+pub(crate) fn csp_rdp_send(node: &csp_node_t, conn: &csp_conn_inner, mut packet: csp_packet_t) -> Result<(), CspError> {
+    let mut rdp = conn.rdp.as_ref().ok_or(CspError::NotSupported(CSP_O_RDP))?.lock().unwrap();
+    let deadline = Instant::now() + rdp.opt.conn_timeout;
+    loop {
+        if rdp.state != RDP_OPEN {
+            return Err(CspError::Reset);
+        }
+        let last = rdp.snd_una.wrapping_add(rdp.window()).wrapping_sub(1);
+        if !csp_rdp_seq_after(rdp.snd_nxt, last) {
+            break;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(CspError::Timeout);
+        }
+        rdp = conn.tx_wait.wait_timeout(rdp, deadline - now).unwrap().0;
+    }
+
+    let (seq_nr, ack_nr) = (rdp.snd_nxt, rdp.rcv_cur);
+    csp_rdp_header_add(&mut packet, RDP_ACK, seq_nr, ack_nr)?;
+    let copy = csp_rdp_clone(node, &packet)?;
+    // only queue for resending what made it out once: a packet without room
+    // for its trailers or without a route would fail the same way every time
+    node.conn_secure(conn, &mut packet)?;
+    node.send_direct(&packet)?;
+    let now = Instant::now();
+    rdp.tx_queue.push_back(csp_rdp_tx_t { packet: copy, first: now, last: now });
+    rdp.snd_nxt = seq_nr.wrapping_add(1);
+    Ok(())
+}
+
+/// Ack after the application read a packet, if one is due
+pub(crate) fn csp_rdp_read(node: &csp_node_t, conn: &csp_conn_inner) {
+    if let Some(rdp) = &conn.rdp {
+        let mut rdp = rdp.lock().unwrap();
+        if rdp.state == RDP_OPEN && rdp.opt.delayed_acks {
+            csp_rdp_check_ack(node, conn, &mut rdp);
+        }
+    }
+}
+
+/// Close, like csp_rdp_close(): an open connection sends RST and waits in
+/// CLOSE_WAIT. Returns true once the connection can be forgotten.
+pub(crate) fn csp_rdp_close(node: &csp_node_t, conn: &csp_conn_inner) -> bool {
+    let mut rdp = match &conn.rdp {
+        Some(rdp) => rdp.lock().unwrap(),
+        None => return true,
+    };
+    rdp.closed = true;
+    match rdp.state {
+        RDP_CLOSED => true,
+        RDP_CLOSE_WAIT => {
+            rdp.state = RDP_CLOSED;
+            true
+        }
+        _ => {
+            rdp.state = RDP_CLOSE_WAIT;
+            rdp.timestamp = Instant::now();
+            let (seq_nr, ack_nr) = (rdp.snd_nxt, rdp.rcv_cur);
+            let _ = csp_rdp_send_cmp(node, conn, &mut rdp, None, RDP_ACK | RDP_RST, seq_nr, ack_nr);
+            conn.tx_wait.notify_all();
+            false
+        }
+    }
+}
+
+/// Resend what timed out, give up on dead connections and send due acks,
+/// like csp_rdp_check_timeouts()
+pub(crate) fn csp_rdp_check_timeouts(node: &csp_node_t, conn: &Arc<csp_conn_inner>) {
+    let mut rdp = match &conn.rdp {
+        Some(rdp) => rdp.lock().unwrap(),
+        None => return,
+    };
+    let now = Instant::now();
+    let opt = rdp.opt;
+
+    // acked packets go first, so they cannot time out the connection
+    let snd_una = rdp.snd_una;
+    rdp.tx_queue.retain(|tx| {
+        let seq_nr = tx.packet.read_u16(tx.packet.length() - 4).unwrap_or(0);
+        !csp_rdp_seq_before(seq_nr, snd_una)
+    });
+
+    match rdp.state {
+        // connect() and the application see to these
+        RDP_CLOSED | RDP_SYN_SENT => {}
+        RDP_CLOSE_WAIT if now.duration_since(rdp.timestamp) > opt.conn_timeout => {
+            rdp.state = RDP_CLOSED;
+            return csp_rdp_release(node, conn, &mut rdp);
+        }
+        _ if rdp.tx_queue.iter().any(|tx| now.duration_since(tx.first) > opt.conn_timeout) => {
+            rdp.state = RDP_CLOSED;
+            return csp_rdp_release(node, conn, &mut rdp);
+        }
+        _ => {}
+    }
+
+    // timed out packets are sent again with the latest ack
+    let rcv_cur = rdp.rcv_cur;
+    for tx in rdp.tx_queue.iter_mut() {
+        if now.duration_since(tx.last) >= opt.packet_timeout {
+            let offset = tx.packet.length() - 2;
+            let _ = tx.packet.write_u16(offset, rcv_cur);
+            // no free buffer: try again on the next check
+            if let Ok(copy) = csp_rdp_clone(node, &tx.packet) {
+                tx.last = now;
+                let _ = node.conn_transmit(conn, copy);
+            }
+        }
+    }
+
+    match rdp.state {
+        RDP_OPEN => {
+            conn.tx_wait.notify_all();
+            if rdp.rcv_cur != rdp.rcv_lsa {
+                csp_rdp_check_ack(node, conn, &mut rdp);
+            }
+        }
+        // our RST may have been lost or overtaken by data still in flight
+        RDP_CLOSE_WAIT if rdp.closed && now.duration_since(rdp.ack_timestamp) >= opt.packet_timeout => {
+            let (seq_nr, ack_nr) = (rdp.snd_nxt, rdp.rcv_cur);
+            let _ = csp_rdp_send_cmp(node, conn, &mut rdp, None, RDP_ACK | RDP_RST, seq_nr, ack_nr);
+        }
+        _ => {}
+    }
+}
+
+/// Handle an incoming packet of an RDP connection, like csp_rdp_new_packet()
+pub(crate) fn csp_rdp_new_packet(node: &csp_node_t, conn: &Arc<csp_conn_inner>, mut packet: csp_packet_t) {
+    let mut rdp = match &conn.rdp {
+        Some(rdp) => rdp.lock().unwrap(),
+        None => return,
+    };
+    let (flags, seq_nr, ack_nr) = match csp_rdp_header_remove(&mut packet) {
+        Some(header) => header,
+        None => return,
+    };
+
+    if flags & RDP_RST != 0 {
+        if flags & RDP_ACK != 0 {
+            rdp.snd_una = ack_nr.wrapping_add(1);
+        }
+        match rdp.state {
+            RDP_CLOSE_WAIT | RDP_CLOSED => {
+                rdp.state = RDP_CLOSED;
+                csp_rdp_release(node, conn, &mut rdp);
+            }
+            // only close once everything before the RST is in
+            _ if seq_nr == rdp.rcv_cur.wrapping_add(1) => {
+                rdp.state = RDP_CLOSE_WAIT;
+                rdp.timestamp = Instant::now();
+                let (seq_nr, ack_nr) = (rdp.snd_nxt, rdp.rcv_cur);
+                let _ = csp_rdp_send_cmp(node, conn, &mut rdp, None, RDP_ACK | RDP_RST, seq_nr, ack_nr);
+                csp_rdp_release(node, conn, &mut rdp);
+            }
+            _ => {}
+        }
+        return;
+    }
+
+    match rdp.state {
+        RDP_CLOSED => {
+            let (snd_nxt, rcv_cur) = (rdp.snd_nxt, rdp.rcv_cur);
+            if flags & RDP_SYN == 0 {
+                let _ = csp_rdp_send_cmp(node, conn, &mut rdp, None, RDP_RST, snd_nxt, rcv_cur);
+                return csp_rdp_release(node, conn, &mut rdp);
+            }
+            rdp.reset_iss();
+            rdp.rcv_cur = seq_nr;
+            rdp.rcv_irs = seq_nr;
+            rdp.rcv_lsa = seq_nr;
+            csp_rdp_read_syn(node, &packet, &mut rdp.opt);
+            rdp.state = RDP_SYN_RCVD;
+            let (iss, irs) = (rdp.snd_iss, rdp.rcv_irs);
+            let _ = csp_rdp_send_cmp(node, conn, &mut rdp, None, RDP_ACK | RDP_SYN, iss, irs);
+        }
+
+        RDP_SYN_SENT => {
+            if flags & RDP_SYN != 0 && flags & RDP_ACK != 0 {
+                rdp.rcv_cur = seq_nr;
+                rdp.rcv_irs = seq_nr;
+                rdp.rcv_lsa = seq_nr.wrapping_sub(1);
+                rdp.snd_una = ack_nr.wrapping_add(1);
+                rdp.ack_timestamp = Instant::now();
+                rdp.state = RDP_OPEN;
+                let (snd_nxt, rcv_cur) = (rdp.snd_nxt, rdp.rcv_cur);
+                let _ = csp_rdp_send_cmp(node, conn, &mut rdp, None, RDP_ACK, snd_nxt, rcv_cur);
+                conn.tx_wait.notify_all();
+            } else if flags & RDP_ACK != 0 {
+                // our SYN hit a connection the peer still has open
+                let (snd_nxt, rcv_cur) = (rdp.snd_nxt, rdp.rcv_cur);
+                let _ = csp_rdp_send_cmp(node, conn, &mut rdp, None, RDP_RST, snd_nxt, rcv_cur);
+                rdp.half_open = true;
+                conn.tx_wait.notify_all();
+            } else {
+                // SYN answered with SYN
+                rdp.state = RDP_CLOSED;
+                csp_rdp_release(node, conn, &mut rdp);
+            }
+        }
+
+        RDP_SYN_RCVD | RDP_OPEN => {
+            if flags & RDP_SYN != 0 || flags & RDP_ACK == 0 {
+                // a resent SYN is harmless, anything else is not
+                if seq_nr != rdp.rcv_irs {
+                    rdp.state = RDP_CLOSED;
+                    csp_rdp_release(node, conn, &mut rdp);
+                }
+                return;
+            }
+
+            let window = rdp.window();
+            let (rcv_cur, snd_una, snd_nxt) = (rdp.rcv_cur, rdp.snd_una, rdp.snd_nxt);
+            if !csp_rdp_seq_between(seq_nr, rcv_cur.wrapping_add(1), rcv_cur.wrapping_add(window.wrapping_mul(2))) {
+                // duplicate: the peer missed our answer
+                if rdp.state == RDP_SYN_RCVD {
+                    let (iss, irs) = (rdp.snd_iss, rdp.rcv_irs);
+                    let _ = csp_rdp_send_cmp(node, conn, &mut rdp, None, RDP_ACK | RDP_SYN, iss, irs);
+                } else {
+                    let _ = csp_rdp_send_eack(node, conn, &mut rdp);
+                }
+                return;
+            }
+            if !csp_rdp_seq_between(ack_nr, snd_una.wrapping_sub(1).wrapping_sub(window.wrapping_mul(2)), snd_nxt.wrapping_sub(1)) {
+                return;
+            }
+
+            if rdp.state == RDP_SYN_RCVD {
+                if ack_nr != rdp.snd_iss {
+                    rdp.state = RDP_CLOSED;
+                    return csp_rdp_release(node, conn, &mut rdp);
+                }
+                rdp.state = RDP_OPEN;
+                // the handshake is done, the connection is ready for accept()
+                if let Some(socket) = rdp.socket.clone() {
+                    if !socket.enqueue(conn.clone()) {
+                        rdp.state = RDP_CLOSED;
+                        return csp_rdp_release(node, conn, &mut rdp);
+                    }
+                    rdp.socket = None;
+                }
+            }
+
+            rdp.snd_una = ack_nr.wrapping_add(1);
+            conn.tx_wait.notify_all();
+
+            if flags & RDP_EAK != 0 {
+                return csp_rdp_flush_eack(&mut rdp, &packet);
+            }
+            if packet.length() == 0 {
+                return;
+            }
+
+            // early: hold it and tell the sender what we have
+            if seq_nr != rdp.rcv_cur.wrapping_add(1) {
+                if rdp.rx_queue.iter().any(|(s, _)| *s == seq_nr) {
+                    return csp_rdp_check_ack(node, conn, &mut rdp);
+                }
+                if rdp.rx_queue.len() >= 2 * window as usize {
+                    return;
+                }
+                rdp.rx_queue.push((seq_nr, packet));
+                let _ = csp_rdp_send_eack(node, conn, &mut rdp);
+                return;
+            }
+
+            // in order; if the application is not keeping up the packet is
+            // dropped and comes again
+            if conn.enqueue(packet).is_err() {
+                return;
+            }
+            rdp.rcv_cur = seq_nr;
+            csp_rdp_check_ack(node, conn, &mut rdp);
+            csp_rdp_rx_queue_flush(conn, &mut rdp);
+        }
+
+        RDP_CLOSE_WAIT => {
+            if flags & RDP_SYN != 0 || flags & RDP_ACK == 0 {
+                return;
+            }
+            rdp.snd_una = ack_nr.wrapping_add(1);
+            let (snd_nxt, rcv_cur) = (rdp.snd_nxt, rdp.rcv_cur);
+            let _ = csp_rdp_send_cmp(node, conn, &mut rdp, None, RDP_ACK | RDP_RST, snd_nxt, rcv_cur);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CSP::csp_test::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Mutex};
+    use std::thread;
+
+    // what the test link does with a packet
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum csp_lossy_action_t {
+        LOSSY_PASS,
+        LOSSY_DROP,
+        LOSSY_HOLD, // send it after the next packet that passes
+    }
+
+    use self::csp_lossy_action_t::*;
+
+    // decides on a packet from its RDP flags, sequence number and payload length
+    type csp_lossy_filter_t = Box<dyn FnMut(u8, u16, usize) -> csp_lossy_action_t + Send>;
+
+    // one direction of a link that loses and reorders packets, and logs what
+    // it was given as (flags, seq_nr, payload length)
+    struct csp_if_lossy_t {
+        tx: Mutex<mpsc::Sender<csp_packet_t>>,
+        rx: Mutex<mpsc::Receiver<csp_packet_t>>,
+        filter: Mutex<csp_lossy_filter_t>,
+        held: Mutex<Option<csp_packet_t>>,
+        log: Mutex<Vec<(u8, u16, usize)>>,
+        stats: csp_iface_stats_t,
+    }
+
+    impl csp_if_lossy_t {
+        fn pair() -> (Arc<csp_if_lossy_t>, Arc<csp_if_lossy_t>) {
+            let (a_tx, a_rx) = mpsc::channel();
+            let (b_tx, b_rx) = mpsc::channel();
+            let new = |tx, rx| {
+                Arc::new(csp_if_lossy_t {
+                    tx: Mutex::new(tx),
+                    rx: Mutex::new(rx),
+                    filter: Mutex::new(Box::new(|_, _, _| LOSSY_PASS)),
+                    held: Mutex::new(None),
+                    log: Mutex::new(Vec::new()),
+                    stats: csp_iface_stats_t::default(),
+                })
+            };
+            (new(a_tx, b_rx), new(b_tx, a_rx))
+        }
+
+        fn set_filter(&self, filter: impl FnMut(u8, u16, usize) -> csp_lossy_action_t + Send + 'static) {
+            *self.filter.lock().unwrap() = Box::new(filter);
+        }
+
+        // times a data packet with `seq_nr` was transmitted
+        fn sent(&self, seq_nr: u16) -> usize {
+            self.log.lock().unwrap().iter().filter(|&&(_, s, len)| s == seq_nr && len > 0).count()
+        }
+
+        // control packets with all of `flags` transmitted
+        fn sent_flags(&self, flags: u8) -> usize {
+            self.log.lock().unwrap().iter().filter(|&&(f, _, _)| f & flags == flags).count()
+        }
+    }
+
+    impl CspInterface for csp_if_lossy_t {
+        fn name(&self) -> &str {
+            "LOSSY"
+        }
+
+        fn mtu(&self) -> usize {
+            CSP_BUFFER_SIZE
+        }
+
+        fn transmit(&self, packet: &csp_packet_t, _via: u16) -> Result<(), CspError> {
+            let offset = packet.length().saturating_sub(CSP_RDP_HEADER_SIZE);
+            let flags = packet.read_u8(offset).unwrap_or(0);
+            let seq_nr = packet.read_u16(offset + 1).unwrap_or(0);
+            self.log.lock().unwrap().push((flags, seq_nr, offset));
+
+            let copy = csp_packet_t::from_slice(packet.id, packet.data());
+            let tx = self.tx.lock().unwrap();
+            match (self.filter.lock().unwrap())(flags, seq_nr, offset) {
+                LOSSY_PASS => {
+                    let _ = tx.send(copy);
+                    if let Some(held) = self.held.lock().unwrap().take() {
+                        let _ = tx.send(held);
+                    }
+                }
+                LOSSY_DROP => {}
+                LOSSY_HOLD => *self.held.lock().unwrap() = Some(copy),
+            }
+            Ok(())
+        }
+
+        fn receive(&self, timeout: Duration) -> Result<csp_packet_t, CspError> {
+            self.rx.lock().unwrap().recv_timeout(timeout).map_err(|_| CspError::Timeout)
+        }
+
+        fn stats(&self) -> &csp_iface_stats_t {
+            &self.stats
+        }
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn csp_rdp_test_opt() -> csp_rdp_opt_t {
+        csp_rdp_opt_t {
+            window_size: 3,
+            conn_timeout: Duration::from_secs(3),
+            packet_timeout: Duration::from_millis(200),
+            delayed_acks: false,
+            ack_timeout: Duration::from_millis(50),
+            ack_delay_count: 2,
+        }
+    }
+
+    // client node 1 and server node 2 on a lossy link, the server listening
+    // on port 10 with the default RDP settings
+    struct csp_rdp_test_t {
+        client: csp_node_t,
+        server: csp_node_t,
+        to_server: Arc<csp_if_lossy_t>,
+        to_client: Arc<csp_if_lossy_t>,
+        socket: Option<csp_socket_t>,
+    }
+
+    impl csp_rdp_test_t {
+        fn new() -> csp_rdp_test_t {
+            csp_rdp_test_t::with_opt(csp_rdp_test_opt())
+        }
+
+        // the client asks for `opt` in its SYN
+        fn with_opt(opt: csp_rdp_opt_t) -> csp_rdp_test_t {
+            let client = csp_node_t::new(csp_conf_t { address: 1, rdp_opt: opt, ..Default::default() }).unwrap();
+            let server = csp_node_t::new(csp_conf_t { address: 2, ..Default::default() }).unwrap();
+            let (to_server, to_client) = csp_if_lossy_t::pair();
+            client.add_interface(to_server.clone()).unwrap();
+            server.add_interface(to_client.clone()).unwrap();
+            client.rtable_load("2 LOSSY").unwrap();
+            server.rtable_load("1 LOSSY").unwrap();
+            client.route_start_task().unwrap();
+            server.route_start_task().unwrap();
+            let socket = server.socket(CSP_SO_RDPREQ).unwrap();
+            socket.bind(10).unwrap();
+            socket.listen(1).unwrap();
+            csp_rdp_test_t { client, server, to_server, to_client, socket: Some(socket) }
+        }
+
+        fn open(&self, opts: u32) -> (csp_conn_t, csp_conn_t) {
+            let client = self.client.connect(csp_prio_t::CSP_PRIO_NORM, 2, 10, TIMEOUT, CSP_O_RDP | opts).unwrap();
+            let server = self.socket.as_ref().unwrap().accept(TIMEOUT).unwrap();
+            (client, server)
+        }
+    }
+
+    impl Drop for csp_rdp_test_t {
+        fn drop(&mut self) {
+            self.socket.take();
+            self.client.stop();
+            self.server.stop();
+        }
+    }
+
+    fn is_data(flags: u8, len: usize) -> bool {
+        flags == RDP_ACK && len > 0
+    }
+
+    fn snd_nxt(conn: &csp_conn_t) -> u16 {
+        conn.inner.rdp.as_ref().unwrap().lock().unwrap().snd_nxt
+    }
+
+    #[test]
+    fn handshake() {
+        let t = csp_rdp_test_t::new();
+        let mut syn_lost = false;
+        t.to_server.set_filter(move |flags, _, _| match flags & RDP_SYN != 0 && !syn_lost {
+            true => {
+                syn_lost = true;
+                LOSSY_DROP
+            }
+            false => LOSSY_PASS,
+        });
+        let mut syn_ack_lost = false;
+        t.to_client.set_filter(move |flags, _, _| match flags & RDP_SYN != 0 && !syn_ack_lost {
+            true => {
+                syn_ack_lost = true;
+                LOSSY_DROP
+            }
+            false => LOSSY_PASS,
+        });
+
+        let (client, server) = t.open(CSP_O_NONE);
+        assert_eq!(client.rdp_state(), Some(RDP_OPEN));
+        assert_eq!(server.rdp_state(), Some(RDP_OPEN));
+        assert!(t.to_server.sent_flags(RDP_SYN) >= 2);
+        assert!(t.to_client.sent_flags(RDP_SYN | RDP_ACK) >= 2);
+        // the server runs with the settings from the client's SYN
+        let opt = server.inner.rdp.as_ref().unwrap().lock().unwrap().opt;
+        assert_eq!(opt.window_size, csp_rdp_test_opt().window_size);
+        assert_eq!(opt.packet_timeout, csp_rdp_test_opt().packet_timeout);
+
+        client.send(packet(b"hello")).unwrap();
+        assert_eq!(server.read(TIMEOUT).unwrap().data(), b"hello");
+    }
+
+    #[test]
+    fn oversized_syn_window() {
+        for &window_size in &[40000, 65536, u32::MAX] {
+            let t = csp_rdp_test_t::with_opt(csp_rdp_opt_t { window_size, ..csp_rdp_test_opt() });
+            let (client, server) = t.open(CSP_O_NONE);
+            // two windows have to fit the server's connection queue
+            let opt = server.inner.rdp.as_ref().unwrap().lock().unwrap().opt;
+            assert_eq!(opt.window_size as usize, (t.server.conf().conn_queue_length - 1) / 2);
+
+            for i in 0..8u8 {
+                client.send(packet(&[i])).unwrap();
+                assert_eq!(server.read(TIMEOUT).unwrap().data(), &[i]);
+                server.send(packet(&[i])).unwrap();
+                assert_eq!(client.read(TIMEOUT).unwrap().data(), &[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn connect_without_server_times_out() {
+        let t = csp_rdp_test_t::new();
+        t.to_server.set_filter(|_, _, _| LOSSY_DROP);
+        let result = t.client.connect(csp_prio_t::CSP_PRIO_NORM, 2, 10, Duration::from_millis(500), CSP_O_RDP);
+        assert!(matches!(result, Err(CspError::Timeout)));
+    }
+
+    #[test]
+    fn retransmit() {
+        let t = csp_rdp_test_t::new();
+        let (client, server) = t.open(CSP_O_NONE);
+        // lose the first copy of the last packet, so only its timeout brings it back
+        let seq_nr = snd_nxt(&client).wrapping_add(2);
+        let mut lost = false;
+        t.to_server.set_filter(move |flags, seq, len| match is_data(flags, len) && seq == seq_nr && !lost {
+            true => {
+                lost = true;
+                LOSSY_DROP
+            }
+            false => LOSSY_PASS,
+        });
+
+        for i in 0..3u8 {
+            client.send(packet(&[i])).unwrap();
+        }
+        for i in 0..3u8 {
+            assert_eq!(server.read(TIMEOUT).unwrap().data(), &[i]);
+        }
+        assert_eq!(t.to_server.sent(seq_nr), 2);
+    }
+
+    #[test]
+    fn eack_reorder() {
+        let t = csp_rdp_test_t::new();
+        let (client, server) = t.open(CSP_O_NONE);
+        // the first packet arrives after the second
+        let seq_nr = snd_nxt(&client);
+        let mut held = false;
+        t.to_server.set_filter(move |flags, seq, len| match is_data(flags, len) && seq == seq_nr && !held {
+            true => {
+                held = true;
+                LOSSY_HOLD
+            }
+            false => LOSSY_PASS,
+        });
+
+        for i in 0..3u8 {
+            client.send(packet(&[i])).unwrap();
+        }
+        for i in 0..3u8 {
+            assert_eq!(server.read(TIMEOUT).unwrap().data(), &[i]);
+        }
+        // the EACK acked the early packet, so only the late one may be sent again
+        assert!(t.to_client.sent_flags(RDP_ACK | RDP_EAK) > 0);
+        assert_eq!(t.to_server.sent(seq_nr.wrapping_add(1)), 1);
+    }
+
+    #[test]
+    fn eack_resends_only_missing() {
+        let t = csp_rdp_test_t::new();
+        let (client, server) = t.open(CSP_O_NONE);
+        let seq_nr = snd_nxt(&client);
+        let mut lost = false;
+        t.to_server.set_filter(move |flags, seq, len| match is_data(flags, len) && seq == seq_nr && !lost {
+            true => {
+                lost = true;
+                LOSSY_DROP
+            }
+            false => LOSSY_PASS,
+        });
+
+        for i in 0..3u8 {
+            client.send(packet(&[i])).unwrap();
+        }
+        for i in 0..3u8 {
+            assert_eq!(server.read(TIMEOUT).unwrap().data(), &[i]);
+        }
+        // the EACK took the two early packets off the client's queue
+        assert_eq!(t.to_server.sent(seq_nr), 2);
+        assert_eq!(t.to_server.sent(seq_nr.wrapping_add(1)), 1);
+        assert_eq!(t.to_server.sent(seq_nr.wrapping_add(2)), 1);
+    }
+
+    #[test]
+    fn window_blocks_sender() {
+        let t = csp_rdp_test_t::new();
+        let (client, server) = t.open(CSP_O_NONE);
+        let blocked = Arc::new(AtomicBool::new(true));
+        let acks = blocked.clone();
+        t.to_client.set_filter(move |_, _, _| if acks.load(Ordering::SeqCst) { LOSSY_DROP } else { LOSSY_PASS });
+
+        let window = csp_rdp_test_opt().window_size as u8;
+        for i in 0..window {
+            client.send(packet(&[i])).unwrap();
+        }
+        let unblock = blocked.clone();
+        let acker = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(500));
+            unblock.store(false, Ordering::SeqCst);
+        });
+        let start = Instant::now();
+        client.send(packet(&[window])).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(450));
+        acker.join().unwrap();
+
+        for i in 0..=window {
+            assert_eq!(server.read(TIMEOUT).unwrap().data(), &[i]);
+        }
+    }
+
+    #[test]
+    fn failed_send_keeps_sequence_number() {
+        let t = csp_rdp_test_t::new();
+        let (client, server) = t.open(CSP_O_CRC32);
+        let seq_nr = snd_nxt(&client);
+
+        // room for the RDP header, not for the CRC after it
+        let mut tight = csp_packet_t::new(1 + CSP_RDP_HEADER_SIZE);
+        tight.append(b"x").unwrap();
+        assert!(matches!(client.send(tight), Err(CspError::TooLarge { .. })));
+        assert_eq!(snd_nxt(&client), seq_nr);
+        assert!(client.inner.rdp.as_ref().unwrap().lock().unwrap().tx_queue.is_empty());
+
+        client.send(packet(b"y")).unwrap();
+        assert_eq!(server.read(TIMEOUT).unwrap().data(), b"y");
+    }
+
+    #[test]
+    fn acked_packet_does_not_time_out() {
+        let opt = csp_rdp_opt_t { conn_timeout: Duration::from_millis(100), ..csp_rdp_test_opt() };
+        let node = csp_node_t::new(csp_conf_t { rdp_opt: opt, ..Default::default() }).unwrap();
+        let idout = csp_id_t::new(node.version(), csp_prio_t::CSP_PRIO_NORM, 1, 2, 10, 30, CSP_FRDP as u8).unwrap();
+        let idin = csp_id_t { src: 2, dst: 1, dport: 30, sport: 10, ..idout };
+        let conn = node.inner.conns.lock().unwrap().new_conn(idin, idout, CSP_O_RDP).unwrap();
+        {
+            let mut rdp = conn.rdp.as_ref().unwrap().lock().unwrap();
+            rdp.state = RDP_OPEN;
+            rdp.snd_una = 5;
+            rdp.snd_nxt = 5;
+            let mut sent = csp_packet_t::new(CSP_RDP_CMP_SIZE);
+            csp_rdp_header_add(&mut sent, RDP_ACK, 4, 0).unwrap();
+            let first = Instant::now().checked_sub(2 * opt.conn_timeout).unwrap();
+            rdp.tx_queue.push_back(csp_rdp_tx_t { packet: sent, first, last: first });
+        }
+        csp_rdp_check_timeouts(&node, &conn);
+        let rdp = conn.rdp.as_ref().unwrap().lock().unwrap();
+        assert_eq!(rdp.state, RDP_OPEN);
+        assert!(rdp.tx_queue.is_empty());
+        drop(rdp);
+        node.stop();
+    }
+
+    #[test]
+    fn close() {
+        let t = csp_rdp_test_t::new();
+        let (client, server) = t.open(CSP_O_NONE);
+        // the first RST is lost, the client sends it again from CLOSE_WAIT
+        let mut lost = false;
+        t.to_server.set_filter(move |flags, _, _| match flags & RDP_RST != 0 && !lost {
+            true => {
+                lost = true;
+                LOSSY_DROP
+            }
+            false => LOSSY_PASS,
+        });
+
+        client.send(packet(b"last")).unwrap();
+        client.close();
+        assert_eq!(server.read(TIMEOUT).unwrap().data(), b"last");
+        assert!(matches!(server.read(TIMEOUT), Err(CspError::Reset)));
+        assert!(matches!(server.send(packet(b"late")), Err(CspError::Reset)));
+        assert!(t.to_server.sent_flags(RDP_RST) >= 2);
+        drop(server);
+
+        thread::sleep(Duration::from_millis(300));
+        assert!(t.client.inner.conns.lock().unwrap().list().is_empty());
+        assert!(t.server.inner.conns.lock().unwrap().list().is_empty());
+    }
+}
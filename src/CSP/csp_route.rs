@@ -0,0 +1,120 @@
+// Incoming packet processing
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/csp_route.c
+//
+// The router takes packets off the node's qfifo one at a time. Packets for
+// this node, or broadcast, go to their connection (csp_conn.rs); everything
+// else is sent on through the routing table, except back out of the
+// interface it came in on.
+// route_work() does one packet, so an application can drive the router from
+// its own loop, or route_start_task() runs it on a thread of its own.
+
+use std::thread;
+use std::time::Duration;
+
+use super::*;
+
+impl csp_node_t {
+    /// Send `packet` out of the interface its destination routes to, like
+    /// csp_send_direct(). Fails with NoRoute if no route matches and with
+    /// TooLarge if the payload exceeds the interface's MTU.
+    pub fn send_direct(&self, packet: &csp_packet_t) -> Result<(), CspError> {
+        let route = self.rtable().find_route(packet.id.dst).cloned().ok_or(CspError::NoRoute(packet.id.dst))?;
+        csp_send_route(&route, packet)
+    }
+
+    /// Route one packet from the qfifo, waiting up to `timeout` for one to
+    /// arrive, like csp_route_work(). Fails with Timeout if none did; packets
+    /// that cannot be forwarded are dropped and counted on their interface.
+    /// RDP timeouts are checked first, so call it at least every packet_timeout.
+    pub fn route_work(&self, timeout: Duration) -> Result<(), CspError> {
+        self.conn_check_timeouts();
+        let csp_qfifo_t { packet, iface } = self.qfifo_read(timeout)?;
+
+        // the broadcast address is the highest one
+        let dst = packet.id.dst;
+        if dst == self.address() || dst == self.version().host_max() {
+            self.deliver(packet, iface);
+            return Ok(());
+        }
+
+        let route = self.rtable().find_route(dst).cloned();
+        match route {
+            // never send a packet back out of the interface it came in on
+            Some(route) if !csp_iface_same(&route.iface, &iface) => {
+                // a failed send is counted by csp_send_route() and the interface
+                let _ = csp_send_route(&route, &packet);
+            }
+            _ => csp_iface_stats_t::add(&iface.stats().drop, 1),
+        }
+        Ok(())
+    }
+
+    /// Run route_work() on a thread of its own until stop(), like csp_route_start_task()
+    pub fn route_start_task(&self) -> Result<(), CspError> {
+        let node = self.clone();
+        let thread = thread::Builder::new().name(String::from("csp_route")).spawn(move || {
+            while node.is_running() {
+                let _ = node.route_work(CSP_NODE_POLL);
+            }
+        })?;
+        self.inner.threads.lock().unwrap().push(thread);
+        Ok(())
+    }
+}
+
+// transmit on a route's interface, checking its MTU first
+fn csp_send_route(route: &csp_route_t, packet: &csp_packet_t) -> Result<(), CspError> {
+    let mtu = route.iface.mtu();
+    if packet.length() > mtu {
+        csp_iface_stats_t::add(&route.iface.stats().tx_error, 1);
+        return Err(CspError::TooLarge { length: packet.length(), max: mtu });
+    }
+    route.iface.transmit(packet, route.via)
+}
+
+/// Apply a socket's CSP_SO_* options to an incoming packet, decrypting,
+/// verifying and stripping whatever its header flags announce.
+/// On success the payload is the one the sender handed to csp_send_security().
+/// A packet without a feature the options require fails with Required(flag).
+pub fn csp_route_security_check(keys: &csp_security_keys_t, security_opts: u32, packet: &mut csp_packet_t) -> Result<(), CspError> {
+    let flags = packet.id.flags as u32;
+
+    if flags & CSP_FXTEA != 0 {
+        if security_opts & CSP_SO_XTEAPROHIB != 0 {
+            return Err(CspError::Prohibited(CSP_FXTEA));
+        }
+        let key = keys.xtea.ok_or(CspError::DecryptFailed)?;
+        csp_xtea_decrypt_packet(&key, packet)?;
+    } else if security_opts & CSP_SO_XTEAREQ != 0 {
+        return Err(CspError::Required(CSP_FXTEA));
+    }
+
+    if flags & CSP_FCRC32 != 0 {
+        if security_opts & CSP_SO_CRC32PROHIB != 0 {
+            return Err(CspError::Prohibited(CSP_FCRC32));
+        }
+        csp_crc32_verify(packet)?;
+    } else if security_opts & CSP_SO_CRC32REQ != 0 {
+        return Err(CspError::Required(CSP_FCRC32));
+    }
+
+    // payload only, like libcsp, for compatibility with csp 1.x nodes
+    if flags & CSP_FHMAC != 0 {
+        if security_opts & CSP_SO_HMACPROHIB != 0 {
+            return Err(CspError::Prohibited(CSP_FHMAC));
+        }
+        let key = keys.hmac.ok_or(CspError::HmacMismatch)?;
+        csp_hmac_verify(&key, packet, false)?;
+    } else if security_opts & CSP_SO_HMACREQ != 0 {
+        return Err(CspError::Required(CSP_FHMAC));
+    }
+
+    if flags & CSP_FRDP != 0 {
+        if security_opts & CSP_SO_RDPPROHIB != 0 {
+            return Err(CspError::Prohibited(CSP_FRDP));
+        }
+    } else if security_opts & CSP_SO_RDPREQ != 0 {
+        return Err(CspError::Required(CSP_FRDP));
+    }
+    Ok(())
+}
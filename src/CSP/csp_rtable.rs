@@ -0,0 +1,407 @@
+// Routing table
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/rtable/csp_rtable_cidr.c
+//
+// Each entry sends the addresses matching address/netmask out of one
+// interface, to the entry's via address or straight to the destination.
+// The longest matching netmask wins, so 0/0 is the default route and a
+// full length netmask is a route to a single node.
+//
+// Tables load from and save to libcsp's route strings (csp_rtable_load/save):
+// comma separated entries of "address[/netmask] interface [via]", e.g.
+// "0/0 CAN, 8 KISS, 10 I2C 10". Without a netmask the entry is a host route.
+
+use std::sync::Arc;
+
+use super::*;
+
+/// One routing table entry, like libcsp's csp_route_t
+#[derive(Clone)]
+pub struct csp_route_t {
+    pub address: u16,                 // !< Destination address
+    pub netmask: u8,                  // !< Leading address bits that must match
+    pub iface: Arc<dyn CspInterface>, // !< Interface the packet leaves on
+    pub via: u16,                     // !< Next hop, CSP_NO_VIA_ADDRESS to send to the destination itself
+}
+
+impl fmt::Debug for csp_route_t {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("csp_route_t")
+            .field("address", &self.address)
+            .field("netmask", &self.netmask)
+            .field("iface", &self.iface.name())
+            .field("via", &self.via)
+            .finish()
+    }
+}
+
+/// Static routing table for one header version
+pub struct csp_rtable_t {
+    version: csp_version_t,
+    routes: Vec<csp_route_t>,
+}
+
+impl csp_rtable_t {
+    /// Empty table for `version` addresses
+    pub fn new(version: csp_version_t) -> csp_rtable_t {
+        csp_rtable_t { version, routes: Vec::new() }
+    }
+
+    pub fn version(&self) -> csp_version_t {
+        self.version
+    }
+
+    /// Netmask of a route to a single node
+    pub fn host_bits(&self) -> u8 {
+        self.version.host_bits()
+    }
+
+    // address bits covered by `netmask`
+    fn mask(&self, netmask: u8) -> u16 {
+        let bits = self.host_bits();
+        if netmask == 0 {
+            0
+        } else {
+            (self.version.host_max() << (bits - netmask)) & self.version.host_max()
+        }
+    }
+
+    /// Add a route, replacing the one for the same address/netmask if any.
+    /// Like csp_rtable_set(). The address bits outside the netmask are
+    /// ignored, so 8/3 and 11/3 are the same route. Fails if the address,
+    /// netmask or via do not fit in a `version` header.
+    pub fn set(&mut self, address: u16, netmask: u8, iface: Arc<dyn CspInterface>, via: u16) -> Result<(), CspError> {
+        let host_max = self.version.host_max();
+        if address > host_max {
+            return Err(CspError::InvalidAddress(address));
+        }
+        if netmask > self.host_bits() {
+            return Err(CspError::InvalidNetmask(netmask));
+        }
+        if via != CSP_NO_VIA_ADDRESS && via > host_max {
+            return Err(CspError::InvalidAddress(via));
+        }
+        let address = address & self.mask(netmask);
+        let route = csp_route_t { address, netmask, iface, via };
+        match self.routes.iter_mut().find(|r| r.address == address && r.netmask == netmask) {
+            Some(existing) => *existing = route,
+            None => self.routes.push(route),
+        }
+        Ok(())
+    }
+
+    /// Route everything without a more specific route through `iface`
+    pub fn set_default(&mut self, iface: Arc<dyn CspInterface>, via: u16) -> Result<(), CspError> {
+        self.set(0, 0, iface, via)
+    }
+
+    /// Remove the route for `address`/`netmask`
+    pub fn delete(&mut self, address: u16, netmask: u8) -> Option<csp_route_t> {
+        let address = address & self.mask(netmask);
+        let index = self.routes.iter().position(|r| r.address == address && r.netmask == netmask)?;
+        Some(self.routes.remove(index))
+    }
+
+    /// Best route to `address`: the matching entry with the longest netmask.
+    /// Like csp_rtable_find_route().
+    pub fn find_route(&self, address: u16) -> Option<&csp_route_t> {
+        self.routes
+            .iter()
+            .filter(|r| {
+                let mask = self.mask(r.netmask);
+                address & mask == r.address & mask
+            })
+            .max_by_key(|r| r.netmask)
+    }
+
+    /// Remove every route, like csp_rtable_clear()
+    pub fn clear(&mut self) {
+        self.routes.clear();
+    }
+
+    /// Routes in insertion order, like csp_rtable_iterate()
+    pub fn iter(&self) -> std::slice::Iter<'_, csp_route_t> {
+        self.routes.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Add the routes of a route string, naming interfaces from `iflist`.
+    /// Like csp_rtable_load(), except that nothing is added unless every entry
+    /// is valid. Returns the number of entries loaded.
+    pub fn load(&mut self, rtable: &str, iflist: &csp_iflist_t) -> Result<usize, CspError> {
+        let mut routes = Vec::new();
+        for entry in rtable.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let route = self.parse_entry(entry, iflist).map_err(|reason| CspError::InvalidRoute {
+                entry: entry.to_string(),
+                reason,
+            })?;
+            routes.push(route);
+        }
+        let count = routes.len();
+        for route in routes {
+            // already checked by parse_entry
+            self.set(route.address, route.netmask, route.iface, route.via)?;
+        }
+        Ok(count)
+    }
+
+    /// The table as a route string, like csp_rtable_save()
+    pub fn save(&self) -> String {
+        self.to_string()
+    }
+
+    // one "address[/netmask] interface [via]" entry, or why it is not one
+    fn parse_entry(&self, entry: &str, iflist: &csp_iflist_t) -> Result<csp_route_t, String> {
+        let fields: Vec<&str> = entry.split_whitespace().collect();
+        if fields.len() < 2 || fields.len() > 3 {
+            return Err(String::from("expected \"address[/netmask] interface [via]\""));
+        }
+        let (address, netmask) = match fields[0].split_once('/') {
+            Some((address, netmask)) => (address, Some(netmask)),
+            None => (fields[0], None),
+        };
+        let address: u16 = address.parse().map_err(|_| format!("bad address \"{}\"", address))?;
+        if address > self.version.host_max() {
+            return Err(format!("address {} above {}", address, self.version.host_max()));
+        }
+        let netmask = match netmask {
+            Some(netmask) => netmask.parse().map_err(|_| format!("bad netmask \"{}\"", netmask))?,
+            None => self.host_bits(),
+        };
+        if netmask > self.host_bits() {
+            return Err(format!("netmask /{} longer than {} address bits", netmask, self.host_bits()));
+        }
+        let iface = iflist
+            .get_by_name(fields[1])
+            .ok_or_else(|| format!("no interface named \"{}\"", fields[1]))?
+            .clone();
+        let via = match fields.get(2) {
+            Some(via) => {
+                let via: u16 = via.parse().map_err(|_| format!("bad via address \"{}\"", via))?;
+                if via > self.version.host_max() {
+                    return Err(format!("via address {} above {}", via, self.version.host_max()));
+                }
+                via
+            }
+            None => CSP_NO_VIA_ADDRESS,
+        };
+        Ok(csp_route_t { address, netmask, iface, via })
+    }
+}
+
+impl fmt::Display for csp_rtable_t {
+    // same format csp_rtable_load() reads back
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, route) in self.routes.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}/{} {}", route.address, route.netmask, route.iface.name())?;
+            if route.via != CSP_NO_VIA_ADDRESS {
+                write!(f, " {}", route.via)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> IntoIterator for &'a csp_rtable_t {
+    type Item = &'a csp_route_t;
+    type IntoIter = std::slice::Iter<'a, csp_route_t>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CSP::csp_test::*;
+    use crate::CSP::csp_version_t::*;
+
+    // name of the interface `address` is routed through, and the via
+    fn hop(rtable: &csp_rtable_t, address: u16) -> Option<(String, u16)> {
+        rtable.find_route(address).map(|r| (r.iface.name().to_string(), r.via))
+    }
+
+    #[test]
+    fn longest_prefix() {
+        let mut rtable = csp_rtable_t::new(CSP_VERSION_1);
+        rtable.set(8, 2, named_iface("WIDE"), CSP_NO_VIA_ADDRESS).unwrap();
+        rtable.set(8, 4, named_iface("NARROW"), CSP_NO_VIA_ADDRESS).unwrap();
+        rtable.set(9, 5, named_iface("HOST"), CSP_NO_VIA_ADDRESS).unwrap();
+
+        assert_eq!(hop(&rtable, 9).unwrap().0, "HOST");
+        assert_eq!(hop(&rtable, 8).unwrap().0, "NARROW");
+        assert_eq!(hop(&rtable, 10).unwrap().0, "WIDE");
+        assert_eq!(hop(&rtable, 15).unwrap().0, "WIDE");
+        assert_eq!(hop(&rtable, 16), None);
+        assert_eq!(hop(&rtable, 7), None);
+    }
+
+    #[test]
+    fn default_route() {
+        let mut rtable = csp_rtable_t::new(CSP_VERSION_1);
+        assert!(rtable.find_route(0).is_none());
+        rtable.set_default(named_iface("CAN"), CSP_NO_VIA_ADDRESS).unwrap();
+        rtable.set(8, 5, named_iface("KISS"), CSP_NO_VIA_ADDRESS).unwrap();
+
+        assert_eq!(hop(&rtable, 8).unwrap().0, "KISS");
+        for address in [0, 7, 9, CSP_ID_HOST_MAX as u16] {
+            assert_eq!(hop(&rtable, address).unwrap().0, "CAN");
+        }
+    }
+
+    #[test]
+    fn via() {
+        let mut rtable = csp_rtable_t::new(CSP_VERSION_1);
+        rtable.set(16, 1, named_iface("I2C"), 10).unwrap();
+        assert_eq!(hop(&rtable, 20), Some((String::from("I2C"), 10)));
+        assert_eq!(hop(&rtable, 15), None);
+
+        let iface = named_iface("I2C");
+        assert!(matches!(rtable.set(1, 5, iface.clone(), 32), Err(CspError::InvalidAddress(32))));
+        rtable.set(1, 5, iface, CSP_ID_HOST_MAX as u16).unwrap();
+    }
+
+    #[test]
+    fn replace_same_key() {
+        let mut rtable = csp_rtable_t::new(CSP_VERSION_1);
+        rtable.set(8, 3, named_iface("CAN"), CSP_NO_VIA_ADDRESS).unwrap();
+        rtable.set(8, 3, named_iface("KISS"), 4).unwrap();
+        assert_eq!(rtable.len(), 1);
+        assert_eq!(hop(&rtable, 9), Some((String::from("KISS"), 4)));
+
+        // same network, host bits set: still the same route
+        rtable.set(11, 3, named_iface("UDP"), CSP_NO_VIA_ADDRESS).unwrap();
+        assert_eq!(rtable.len(), 1);
+        assert_eq!(rtable.iter().next().unwrap().address, 8);
+        assert_eq!(hop(&rtable, 9).unwrap().0, "UDP");
+        assert_eq!(rtable.save(), "8/3 UDP");
+
+        // same address, other netmask: a route of its own
+        rtable.set(8, 2, named_iface("CAN"), CSP_NO_VIA_ADDRESS).unwrap();
+        assert_eq!(rtable.len(), 2);
+
+        assert!(rtable.delete(10, 3).is_some());
+        assert!(rtable.delete(8, 3).is_none());
+        assert_eq!(hop(&rtable, 9).unwrap().0, "CAN");
+        rtable.clear();
+        assert!(rtable.is_empty());
+    }
+
+    #[test]
+    fn version_2_netmask() {
+        let mut rtable = csp_rtable_t::new(CSP_VERSION_2);
+        assert_eq!(rtable.host_bits(), CSP_ID2_HOST_SIZE);
+        let host_max = CSP_ID2_HOST_MAX as u16;
+
+        rtable.set(0x1000, 4, named_iface("ZMQ"), CSP_NO_VIA_ADDRESS).unwrap();
+        rtable.set(0x1234, 12, named_iface("CAN"), 0x1001).unwrap();
+        rtable.set(0x1234, CSP_ID2_HOST_SIZE, named_iface("LOOP"), CSP_NO_VIA_ADDRESS).unwrap();
+
+        assert_eq!(hop(&rtable, 0x1234).unwrap().0, "LOOP");
+        assert_eq!(hop(&rtable, 0x1235), Some((String::from("CAN"), 0x1001)));
+        assert_eq!(hop(&rtable, 0x1300).unwrap().0, "ZMQ");
+        assert_eq!(hop(&rtable, 0x0FFF), None);
+        assert_eq!(hop(&rtable, host_max), None);
+
+        let iface = named_iface("CAN");
+        assert!(matches!(rtable.set(host_max + 1, 0, iface.clone(), CSP_NO_VIA_ADDRESS), Err(CspError::InvalidAddress(_))));
+        assert!(matches!(
+            rtable.set(0, CSP_ID2_HOST_SIZE + 1, iface.clone(), CSP_NO_VIA_ADDRESS),
+            Err(CspError::InvalidNetmask(_))
+        ));
+        // fits a v2 header, not a v1 one
+        csp_rtable_t::new(CSP_VERSION_2).set(100, 5, iface.clone(), 40).unwrap();
+        assert!(csp_rtable_t::new(CSP_VERSION_1).set(100, 5, iface, CSP_NO_VIA_ADDRESS).is_err());
+    }
+
+    fn iflist() -> csp_iflist_t {
+        let mut iflist = csp_iflist_t::new();
+        for name in ["CAN", "KISS", "I2C"] {
+            iflist.add(named_iface(name));
+        }
+        iflist
+    }
+
+    #[test]
+    fn load() {
+        let mut rtable = csp_rtable_t::new(CSP_VERSION_1);
+        assert_eq!(rtable.load("0/0 CAN, 8 KISS, 10 I2C 10", &iflist()).unwrap(), 3);
+        assert_eq!(rtable.len(), 3);
+        assert_eq!(hop(&rtable, 8), Some((String::from("KISS"), CSP_NO_VIA_ADDRESS)));
+        assert_eq!(hop(&rtable, 10), Some((String::from("I2C"), 10)));
+        assert_eq!(hop(&rtable, 9), Some((String::from("CAN"), CSP_NO_VIA_ADDRESS)));
+        let host = rtable.iter().find(|r| r.address == 8).unwrap();
+        assert_eq!(host.netmask, CSP_ID_HOST_SIZE);
+
+        // interface names ignore case, empty entries are skipped
+        assert_eq!(rtable.load(" 16/1 kiss ,, ", &iflist()).unwrap(), 1);
+        assert_eq!(hop(&rtable, 20).unwrap().0, "KISS");
+        assert_eq!(rtable.load("", &iflist()).unwrap(), 0);
+    }
+
+    // load `bad` after a good entry: must fail naming `bad` and load nothing
+    fn load_fails(rtable: &str, bad: &str) {
+        let mut table = csp_rtable_t::new(CSP_VERSION_1);
+        match table.load(rtable, &iflist()) {
+            Err(CspError::InvalidRoute { entry, .. }) => assert_eq!(entry, bad),
+            other => panic!("{:?} loading \"{}\"", other.map(|_| ()), rtable),
+        }
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn load_bad_address() {
+        load_fails("0/0 CAN, x KISS", "x KISS");
+        load_fails("0/0 CAN, -1 KISS", "-1 KISS");
+        load_fails("0/0 CAN, 32 KISS", "32 KISS");
+    }
+
+    #[test]
+    fn load_bad_netmask() {
+        load_fails("0/0 CAN, 8/x KISS", "8/x KISS");
+        load_fails("0/0 CAN, 8/ KISS", "8/ KISS");
+        load_fails("0/0 CAN, 8/6 KISS", "8/6 KISS");
+    }
+
+    #[test]
+    fn load_bad_via() {
+        load_fails("0/0 CAN, 8 KISS x", "8 KISS x");
+        load_fails("0/0 CAN, 8 KISS 32", "8 KISS 32");
+    }
+
+    #[test]
+    fn load_unknown_interface() {
+        load_fails("0/0 CAN, 8 UDP", "8 UDP");
+    }
+
+    #[test]
+    fn load_field_count() {
+        load_fails("0/0 CAN, 8", "8");
+        load_fails("0/0 CAN, 8 KISS 10 10", "8 KISS 10 10");
+    }
+
+    #[test]
+    fn save_load() {
+        let mut rtable = csp_rtable_t::new(CSP_VERSION_1);
+        rtable.load("0/0 CAN, 8 KISS, 10 I2C 10, 16/2 KISS 4", &iflist()).unwrap();
+        let saved = rtable.save();
+        assert_eq!(saved, "0/0 CAN, 8/5 KISS, 10/5 I2C 10, 16/2 KISS 4");
+
+        let mut copy = csp_rtable_t::new(CSP_VERSION_1);
+        assert_eq!(copy.load(&saved, &iflist()).unwrap(), rtable.len());
+        assert_eq!(copy.save(), saved);
+        for address in 0..=CSP_ID_HOST_MAX as u16 {
+            assert_eq!(hop(&copy, address), hop(&rtable, address));
+        }
+    }
+}
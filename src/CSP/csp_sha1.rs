@@ -0,0 +1,137 @@
+// SHA1, used by HMAC (csp_hmac.rs) and for deriving keys
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/crypto/csp_sha1.c
+
+/// Length of a SHA1 digest in bytes
+pub const CSP_SHA1_DIGESTSIZE: usize = 20;
+
+const CSP_SHA1_BLOCKSIZE: usize = 64;
+
+/// Incremental SHA1 state: `new()`, `process()` any number of times, `done()`
+#[derive(Clone)]
+pub struct csp_sha1_state {
+    length: u64,
+    state: [u32; 5],
+    curlen: usize,
+    buf: [u8; CSP_SHA1_BLOCKSIZE],
+}
+
+impl Default for csp_sha1_state {
+    fn default() -> csp_sha1_state {
+        csp_sha1_state::new()
+    }
+}
+
+impl csp_sha1_state {
+    pub fn new() -> csp_sha1_state {
+        csp_sha1_state {
+            length: 0,
+            state: [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0],
+            curlen: 0,
+            buf: [0u8; CSP_SHA1_BLOCKSIZE],
+        }
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 80];
+        for (i, word) in self.buf.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, &wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let t = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(wi);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+
+        for (s, v) in self.state.iter_mut().zip(&[a, b, c, d, e]) {
+            *s = s.wrapping_add(*v);
+        }
+    }
+
+    pub fn process(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let n = (CSP_SHA1_BLOCKSIZE - self.curlen).min(data.len());
+            self.buf[self.curlen..self.curlen + n].copy_from_slice(&data[..n]);
+            self.curlen += n;
+            self.length += (n as u64) * 8;
+            data = &data[n..];
+            if self.curlen == CSP_SHA1_BLOCKSIZE {
+                self.compress();
+                self.curlen = 0;
+            }
+        }
+    }
+
+    pub fn done(mut self) -> [u8; CSP_SHA1_DIGESTSIZE] {
+        let length = self.length;
+
+        // append the '1' bit, pad with zeros and finish with the bit length
+        self.buf[self.curlen] = 0x80;
+        self.curlen += 1;
+        if self.curlen > CSP_SHA1_BLOCKSIZE - 8 {
+            for byte in &mut self.buf[self.curlen..] {
+                *byte = 0;
+            }
+            self.compress();
+            self.curlen = 0;
+        }
+        for byte in &mut self.buf[self.curlen..CSP_SHA1_BLOCKSIZE - 8] {
+            *byte = 0;
+        }
+        self.buf[CSP_SHA1_BLOCKSIZE - 8..].copy_from_slice(&length.to_be_bytes());
+        self.compress();
+
+        let mut digest = [0u8; CSP_SHA1_DIGESTSIZE];
+        for (out, word) in digest.chunks_mut(4).zip(&self.state) {
+            out.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+}
+
+/// SHA1 of `data`
+pub fn csp_sha1_memory(data: &[u8]) -> [u8; CSP_SHA1_DIGESTSIZE] {
+    let mut sha1 = csp_sha1_state::new();
+    sha1.process(data);
+    sha1.done()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CSP::csp_test::*;
+
+    // FIPS 180 examples
+    #[test]
+    fn known_answer() {
+        assert_eq!(hex(&csp_sha1_memory(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(
+            hex(&csp_sha1_memory(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+        assert_eq!(hex(&csp_sha1_memory(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    }
+
+    #[test]
+    fn incremental() {
+        let data = [b'a'; 1000];
+        let mut sha1 = csp_sha1_state::new();
+        for chunk in data.chunks(7) {
+            sha1.process(chunk);
+        }
+        assert_eq!(sha1.done(), csp_sha1_memory(&data));
+    }
+}
@@ -0,0 +1,64 @@
+// Helpers shared by the unit tests
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::*;
+
+/// Header of test packets: node 1, port 20 to node 2, port 10
+pub(crate) const CSP_TEST_ID: csp_id_t =
+    csp_id_t { pri: csp_prio_t::CSP_PRIO_NORM, src: 1, dst: 2, dport: 10, sport: 20, flags: 0 };
+
+/// Packet with CSP_TEST_ID carrying `data`
+pub(crate) fn packet(data: &[u8]) -> csp_packet_t {
+    csp_packet_t::from_slice(CSP_TEST_ID, data)
+}
+
+/// Packet with CSP_TEST_ID, but from `src`, carrying `data`
+pub(crate) fn packet_from(src: u16, data: &[u8]) -> csp_packet_t {
+    csp_packet_t::from_slice(csp_id_t { src, ..CSP_TEST_ID }, data)
+}
+
+/// `length` bytes counting up from 0
+pub(crate) fn payload(length: usize) -> Vec<u8> {
+    (0..length).map(|i| i as u8).collect()
+}
+
+/// `bytes` as lower case hex, for comparing with published test vectors
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Interface that only has a name, for routing table tests. Transmit
+/// discards the packet and receive never returns one.
+pub(crate) struct csp_if_named_t {
+    name: String,
+    stats: csp_iface_stats_t,
+}
+
+impl CspInterface for csp_if_named_t {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn mtu(&self) -> usize {
+        CSP_BUFFER_SIZE
+    }
+
+    fn transmit(&self, _packet: &csp_packet_t, _via: u16) -> Result<(), CspError> {
+        Ok(())
+    }
+
+    fn receive(&self, _timeout: Duration) -> Result<csp_packet_t, CspError> {
+        Err(CspError::Timeout)
+    }
+
+    fn stats(&self) -> &csp_iface_stats_t {
+        &self.stats
+    }
+}
+
+/// csp_if_named_t called `name`
+pub(crate) fn named_iface(name: &str) -> Arc<dyn CspInterface> {
+    Arc::new(csp_if_named_t { name: name.to_string(), stats: csp_iface_stats_t::default() })
+}
@@ -0,0 +1,344 @@
+// Serial port driver for the KISS interface
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/drivers/usart/usart_linux.c
+//
+// The port is shared between the caller, who writes whole KISS frames, and a
+// reader thread that polls it with a short timeout and feeds every byte to a
+// csp_kiss_decoder_t. Decoded packets are queued until receive() picks them up.
+// serial opens the tty exclusively, so both sides go through one handle.
+// Past open() the driver only reads and writes the port, so tests can stand
+// in for the tty.
+
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serial::SerialPort;
+
+use super::*;
+
+// how long the reader thread holds the port waiting for bytes
+const CSP_USART_POLL: Duration = Duration::from_millis(10);
+
+/// Serial port settings, like libcsp's csp_usart_conf_t
+#[derive(Debug, Clone)]
+pub struct csp_usart_conf_t {
+    pub name: String,                      // !< Interface name
+    pub device: String,                    // !< Device path, e.g. /dev/ttyUSB0
+    pub baudrate: usize,                   // !< Bits per second
+    pub databits: serial::CharSize,        // !< Bits per character
+    pub stopbits: serial::StopBits,        // !< Stop bits
+    pub paritysetting: serial::Parity,     // !< Parity
+    pub flow_control: serial::FlowControl, // !< Hardware, software or no flow control
+    pub write_timeout: Duration,           // !< Give up on a frame not written within this time
+    pub version: csp_version_t,            // !< Header version spoken on the link
+    pub mtu: usize,                        // !< Largest payload accepted from the link
+}
+
+impl Default for csp_usart_conf_t {
+    // 115200 8N1, no flow control, like the libcsp examples
+    fn default() -> csp_usart_conf_t {
+        csp_usart_conf_t {
+            name: String::from("KISS"),
+            device: String::from("/dev/ttyUSB0"),
+            baudrate: 115200,
+            databits: serial::Bits8,
+            stopbits: serial::Stop1,
+            paritysetting: serial::ParityNone,
+            flow_control: serial::FlowNone,
+            write_timeout: Duration::from_secs(1),
+            version: csp_version_t::default(),
+            mtu: CSP_BUFFER_SIZE,
+        }
+    }
+}
+
+// what the driver needs from the port once it is configured
+trait csp_usart_port_t: Read + Write + Send {}
+
+impl<T: Read + Write + Send> csp_usart_port_t for T {}
+
+struct csp_usart_shared {
+    port: Mutex<Box<dyn csp_usart_port_t>>,
+    writers: AtomicUsize, // writers waiting for the port; the reader backs off while non zero
+    running: AtomicBool,
+    stats: csp_iface_stats_t,
+}
+
+/// KISS interface on an open serial port, with its reader thread.
+///
+/// Dropping it stops the reader thread and closes the port.
+pub struct csp_usart_t {
+    conf: csp_usart_conf_t,
+    shared: Arc<csp_usart_shared>,
+    rx: Mutex<mpsc::Receiver<csp_packet_t>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl csp_usart_t {
+    /// Open and configure `conf.device` and start the reader thread, which
+    /// decodes into `node`'s buffers
+    pub fn open(node: &csp_node_t, conf: csp_usart_conf_t) -> Result<csp_usart_t, CspError> {
+        let mut port = serial::open(&conf.device)?;
+        port.configure(&serial::PortSettings {
+            baud_rate: serial::BaudRate::from_speed(conf.baudrate),
+            char_size: conf.databits,
+            parity: conf.paritysetting,
+            stop_bits: conf.stopbits,
+            flow_control: conf.flow_control,
+        })?;
+        port.set_timeout(CSP_USART_POLL)?;
+        csp_usart_t::start(node, conf, Box::new(port))
+    }
+
+    // start the reader thread on a configured port that times out reads
+    // after CSP_USART_POLL
+    fn start(node: &csp_node_t, conf: csp_usart_conf_t, port: Box<dyn csp_usart_port_t>) -> Result<csp_usart_t, CspError> {
+        let shared = Arc::new(csp_usart_shared {
+            port: Mutex::new(port),
+            writers: AtomicUsize::new(0),
+            running: AtomicBool::new(true),
+            stats: csp_iface_stats_t::default(),
+        });
+        let (tx, rx) = mpsc::channel();
+        let kiss = csp_kiss_decoder_t::new(conf.version, conf.mtu, node.buffer_pool().clone());
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name(format!("csp_usart {}", conf.device))
+                .spawn(move || csp_usart_rx_task(shared, kiss, tx))?
+        };
+
+        Ok(csp_usart_t {
+            conf,
+            shared,
+            rx: Mutex::new(rx),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn conf(&self) -> &csp_usart_conf_t {
+        &self.conf
+    }
+
+    // write one KISS frame, giving up at the deadline
+    fn write_frame(&self, packet: &csp_packet_t) -> Result<(), CspError> {
+        let frame = csp16u_UART(packet, self.conf.version)?;
+        let deadline = Instant::now() + self.conf.write_timeout;
+
+        self.shared.writers.fetch_add(1, Ordering::SeqCst);
+        let mut port = self.shared.port.lock().unwrap();
+        self.shared.writers.fetch_sub(1, Ordering::SeqCst);
+
+        let mut written = 0;
+        while written < frame.len() {
+            match port.write(&frame[written..]) {
+                Ok(0) => return Err(CspError::Io(std::io::ErrorKind::WriteZero.into())),
+                Ok(n) => written += n,
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(CspError::Io(e)),
+            }
+            if written < frame.len() && Instant::now() >= deadline {
+                return Err(CspError::Timeout);
+            }
+        }
+        port.flush()?;
+        Ok(())
+    }
+}
+
+impl CspInterface for csp_usart_t {
+    fn name(&self) -> &str {
+        &self.conf.name
+    }
+
+    fn mtu(&self) -> usize {
+        self.conf.mtu
+    }
+
+    /// Write `packet` as one KISS frame. A serial link has no addressing, so
+    /// `via` is ignored. Fails with Timeout if the frame is not fully written
+    /// within `write_timeout`; the peer drops the partial frame.
+    fn transmit(&self, packet: &csp_packet_t, _via: u16) -> Result<(), CspError> {
+        match self.write_frame(packet) {
+            Ok(()) => {
+                self.shared.stats.count_tx(packet.length());
+                Ok(())
+            }
+            Err(e) => {
+                csp_iface_stats_t::add(&self.shared.stats.tx_error, 1);
+                Err(e)
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for the next decoded packet.
+    /// Fails with Io(BrokenPipe) once the reader thread has stopped on a port error.
+    fn receive(&self, timeout: Duration) -> Result<csp_packet_t, CspError> {
+        match self.rx.lock().unwrap().recv_timeout(timeout) {
+            Ok(packet) => Ok(packet),
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(CspError::Timeout),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(CspError::Io(std::io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn stats(&self) -> &csp_iface_stats_t {
+        &self.shared.stats
+    }
+}
+
+impl Drop for csp_usart_t {
+    fn drop(&mut self) {
+        self.shared.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// reader thread: poll the port, feed the decoder, queue complete packets.
+// Stops when the interface is dropped or the port fails (e.g. adapter unplugged).
+fn csp_usart_rx_task(shared: Arc<csp_usart_shared>, mut kiss: csp_kiss_decoder_t, tx: mpsc::Sender<csp_packet_t>) {
+    let mut buf = [0u8; 64];
+    let mut last = kiss.stats();
+    while shared.running.load(Ordering::SeqCst) {
+        if shared.writers.load(Ordering::SeqCst) > 0 {
+            thread::yield_now();
+            continue;
+        }
+        let result = shared.port.lock().unwrap().read(&mut buf);
+        let n = match result {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut || e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        for &byte in &buf[..n] {
+            if let Some(packet) = kiss.feed(byte) {
+                shared.stats.count_rx(packet.length());
+                // nobody listening any more: count it and keep draining until stopped
+                if tx.send(packet).is_err() {
+                    csp_iface_stats_t::add(&shared.stats.drop, 1);
+                }
+            }
+        }
+        let now = kiss.stats();
+        csp_iface_stats_t::add(&shared.stats.frame, now.frame - last.frame);
+        csp_iface_stats_t::add(&shared.stats.rx_error, (now.rx_error - last.rx_error) + (now.overrun - last.overrun));
+        csp_iface_stats_t::add(&shared.stats.drop, now.drop - last.drop);
+        last = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CSP::csp_test::*;
+
+    const WAIT: Duration = Duration::from_secs(1);
+
+    // stands in for the tty: reads what the test sends, timing out like a
+    // port would, and keeps what the driver writes
+    struct csp_usart_mock_t {
+        rx: mpsc::Receiver<Vec<u8>>,
+        pending: Vec<u8>,
+        written: Arc<Mutex<Vec<u8>>>,
+        stalled: bool, // never take a byte, like a port held up by flow control
+    }
+
+    impl Read for csp_usart_mock_t {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.pending.is_empty() {
+                self.pending = match self.rx.recv_timeout(CSP_USART_POLL) {
+                    Ok(bytes) => bytes,
+                    Err(mpsc::RecvTimeoutError::Timeout) => return Err(std::io::ErrorKind::TimedOut.into()),
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return Err(std::io::ErrorKind::BrokenPipe.into()),
+                };
+            }
+            let n = buf.len().min(self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            Ok(n)
+        }
+    }
+
+    impl Write for csp_usart_mock_t {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.stalled {
+                thread::sleep(CSP_USART_POLL);
+                return Err(std::io::ErrorKind::TimedOut.into());
+            }
+            self.written.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    type csp_usart_written_t = Arc<Mutex<Vec<u8>>>;
+
+    // driver on a mock port; returns the sender feeding its reads and what it wrote
+    fn start(node: &csp_node_t, conf: csp_usart_conf_t, stalled: bool) -> (csp_usart_t, mpsc::Sender<Vec<u8>>, csp_usart_written_t) {
+        let (tx, rx) = mpsc::channel();
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let port = csp_usart_mock_t { rx, pending: Vec::new(), written: written.clone(), stalled };
+        (csp_usart_t::start(node, conf, Box::new(port)).unwrap(), tx, written)
+    }
+
+    #[test]
+    fn reader() {
+        let node = csp_node_t::new(csp_conf_t::default()).unwrap();
+        let (usart, tx, _) = start(&node, csp_usart_conf_t::default(), false);
+        let version = usart.conf().version;
+
+        let first = csp16u_UART(&packet(b"first"), version).unwrap();
+        let mut corrupt = csp16u_UART(&packet(b"corrupt"), version).unwrap();
+        let at = corrupt.windows(7).position(|w| w == b"corrupt").unwrap();
+        corrupt[at] = b'C';
+        let last = csp16u_UART(&packet(&payload(200)), version).unwrap();
+
+        // frames split across reads at odd places
+        let stream: Vec<u8> = [first, corrupt, last].concat();
+        for chunk in stream.chunks(7) {
+            tx.send(chunk.to_vec()).unwrap();
+        }
+        assert_eq!(usart.receive(WAIT).unwrap().data(), b"first");
+        let received = usart.receive(WAIT).unwrap();
+        assert_eq!((received.id, received.data()), (CSP_TEST_ID, &payload(200)[..]));
+        assert!(matches!(usart.receive(Duration::from_millis(50)), Err(CspError::Timeout)));
+        assert_eq!(usart.stats().rx.load(Ordering::Relaxed), 2);
+        assert_eq!(usart.stats().rx_error.load(Ordering::Relaxed), 1);
+
+        // the port going away stops the reader
+        drop(tx);
+        assert!(matches!(usart.receive(WAIT), Err(CspError::Io(ref e)) if e.kind() == std::io::ErrorKind::BrokenPipe));
+    }
+
+    #[test]
+    fn transmit() {
+        let node = csp_node_t::new(csp_conf_t::default()).unwrap();
+        let (usart, _tx, written) = start(&node, csp_usart_conf_t::default(), false);
+        let sent = packet(b"hello");
+        usart.transmit(&sent, CSP_NO_VIA_ADDRESS).unwrap();
+        assert_eq!(*written.lock().unwrap(), csp16u_UART(&sent, usart.conf().version).unwrap());
+        assert_eq!(usart.stats().tx.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn write_timeout() {
+        let node = csp_node_t::new(csp_conf_t::default()).unwrap();
+        let conf = csp_usart_conf_t { write_timeout: Duration::from_millis(50), ..Default::default() };
+        let (usart, _tx, written) = start(&node, conf, true);
+
+        let start = Instant::now();
+        assert!(matches!(usart.transmit(&packet(b"hello"), CSP_NO_VIA_ADDRESS), Err(CspError::Timeout)));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(start.elapsed() < WAIT);
+        assert!(written.lock().unwrap().is_empty());
+        assert_eq!(usart.stats().tx_error.load(Ordering::Relaxed), 1);
+    }
+}
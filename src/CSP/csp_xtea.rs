@@ -0,0 +1,141 @@
+// XTEA payload encryption in counter mode, as used by libcsp for CSP_FXTEA
+// https://github.com/libcsp/libcsp/blob/libcsp-1-6/src/crypto/csp_xtea.c
+//
+// The sender picks a random 32 bit nonce, encrypts the payload with the
+// counter starting at {nonce, 1} and appends the nonce in network byte order.
+// The key belongs to the node, see csp_conf_t::xtea_key.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use super::*;
+
+/// Bytes added to the payload by csp_xtea_encrypt_packet() (the nonce)
+pub const CSP_XTEA_NONCE_LENGTH: usize = 4;
+
+const XTEA_BLOCKSIZE: usize = 8;
+const XTEA_ROUNDS: u32 = 32;
+const XTEA_KEY_LENGTH: usize = 16;
+
+/// Key actually used for the key shared with the other nodes: like libcsp's
+/// csp_xtea_set_key(), the first 16 bytes of the SHA1 of `key`, loaded as
+/// big endian words the same way as the blocks
+pub fn csp_xtea_key(key: &[u8]) -> [u32; 4] {
+    let hash = csp_sha1_memory(key);
+    let mut k = [0u32; 4];
+    for (word, bytes) in k.iter_mut().zip(hash[..XTEA_KEY_LENGTH].chunks(4)) {
+        *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    k
+}
+
+// LOAD32H/STORE32H in libcsp: block and key words are big endian on any host
+fn csp_xtea_encrypt_block(block: &mut [u8; XTEA_BLOCKSIZE], k: &[u32; 4]) {
+    let mut v0 = u32::from_be_bytes([block[0], block[1], block[2], block[3]]);
+    let mut v1 = u32::from_be_bytes([block[4], block[5], block[6], block[7]]);
+    let delta = 0x9E37_79B9u32;
+    let mut sum = 0u32;
+    for _ in 0..XTEA_ROUNDS {
+        v0 = v0.wrapping_add((((v1 << 4) ^ (v1 >> 5)).wrapping_add(v1)) ^ sum.wrapping_add(k[(sum & 3) as usize]));
+        sum = sum.wrapping_add(delta);
+        v1 = v1.wrapping_add((((v0 << 4) ^ (v0 >> 5)).wrapping_add(v0)) ^ sum.wrapping_add(k[((sum >> 11) & 3) as usize]));
+    }
+    block[..4].copy_from_slice(&v0.to_be_bytes());
+    block[4..].copy_from_slice(&v1.to_be_bytes());
+}
+
+// counter block, csp_htobe32() of both words as in libcsp
+fn csp_xtea_counter(iv: [u32; 2]) -> [u8; XTEA_BLOCKSIZE] {
+    let mut block = [0u8; XTEA_BLOCKSIZE];
+    block[..4].copy_from_slice(&iv[0].to_be_bytes());
+    block[4..].copy_from_slice(&iv[1].to_be_bytes());
+    block
+}
+
+/// Encrypt or decrypt `data` in place (CTR mode is symmetric) with `key` from
+/// csp_xtea_key() and the counter starting at `iv`
+pub fn csp_xtea_encrypt(key: &[u32; 4], data: &mut [u8], iv: [u32; 2]) {
+    let mut counter = iv;
+    for (i, chunk) in data.chunks_mut(XTEA_BLOCKSIZE).enumerate() {
+        let mut stream = csp_xtea_counter(counter);
+        csp_xtea_encrypt_block(&mut stream, key);
+        for (byte, key) in chunk.iter_mut().zip(&stream) {
+            *byte ^= key;
+        }
+        // libcsp post-increments the counter, so the first two blocks share
+        // {nonce, 1}; kept as is so C nodes can decrypt us
+        counter = [iv[0], iv[1].wrapping_add(i as u32)];
+    }
+}
+
+pub fn csp_xtea_decrypt(key: &[u32; 4], data: &mut [u8], iv: [u32; 2]) {
+    csp_xtea_encrypt(key, data, iv)
+}
+
+// not cryptographically strong, but a nonce only has to be unlikely to repeat
+fn csp_xtea_nonce() -> u32 {
+    static CSP_XTEA_COUNTER: AtomicU32 = AtomicU32::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(CSP_XTEA_COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish() as u32
+}
+
+/// Encrypt the payload with `key` and append the nonce
+pub fn csp_xtea_encrypt_packet(key: &[u32; 4], packet: &mut csp_packet_t) -> Result<(), CspError> {
+    if packet.length() + CSP_XTEA_NONCE_LENGTH > packet.capacity() {
+        return Err(CspError::TooLarge { length: packet.length() + CSP_XTEA_NONCE_LENGTH, max: packet.capacity() });
+    }
+    let nonce = csp_xtea_nonce();
+    csp_xtea_encrypt(key, packet.data_mut(), [nonce, 1]);
+    packet.append(&nonce.to_be_bytes())
+}
+
+/// Strip the nonce and decrypt the payload with `key`
+pub fn csp_xtea_decrypt_packet(key: &[u32; 4], packet: &mut csp_packet_t) -> Result<(), CspError> {
+    let length = packet.length().checked_sub(CSP_XTEA_NONCE_LENGTH).ok_or(CspError::DecryptFailed)?;
+    let nonce = packet.read_u32(length).ok_or(CspError::DecryptFailed)?;
+    packet.set_length(length)?;
+    csp_xtea_decrypt(key, packet.data_mut(), [nonce, 1]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CSP::csp_test::*;
+
+    #[test]
+    fn block() {
+        // reference XTEA vector: key 00..0f, plaintext "ABCDEFGH"
+        let key = [0x0001_0203, 0x0405_0607, 0x0809_0a0b, 0x0c0d_0e0f];
+        let mut block = *b"ABCDEFGH";
+        csp_xtea_encrypt_block(&mut block, &key);
+        assert_eq!(hex(&block), "497df3d072612cb5");
+    }
+
+    #[test]
+    fn known_answer() {
+        // csp_xtea_set_key("csp", 3), then csp_xtea_encrypt() with iv {0x01020304, 1}
+        let key = csp_xtea_key(b"csp");
+        let mut data = *b"Hello from CSP!, XTEA";
+        csp_xtea_encrypt(&key, &mut data, [0x0102_0304, 1]);
+        assert_eq!(hex(&data), "60fd34beae3d891d47f57891924dce435e5f94341c");
+        csp_xtea_decrypt(&key, &mut data, [0x0102_0304, 1]);
+        assert_eq!(&data, b"Hello from CSP!, XTEA");
+    }
+
+    #[test]
+    fn packet_round_trip() {
+        let key = csp_xtea_key(b"csp");
+        let mut packet = packet(b"secret payload");
+        csp_xtea_encrypt_packet(&key, &mut packet).unwrap();
+        assert_eq!(packet.length(), 14 + CSP_XTEA_NONCE_LENGTH);
+        assert_ne!(&packet.data()[..14], b"secret payload");
+        csp_xtea_decrypt_packet(&key, &mut packet).unwrap();
+        assert_eq!(packet.data(), b"secret payload");
+
+        let mut short = csp_packet_t::from_slice(csp_id_t::default(), b"abc");
+        assert!(matches!(csp_xtea_decrypt_packet(&key, &mut short), Err(CspError::DecryptFailed)));
+    }
+}
@@ -5,10 +5,22 @@ use socketcan::CANSocket;
 use serial::prelude::*;
 
 pub mod CSP {
+    use std::convert::TryFrom;
+    use std::fmt;
+
+    // crate-wide error type, see csp_error.rs
+    mod csp_error;
+    pub use self::csp_error::*;
+
+    // helpers shared by the unit tests, see csp_test.rs
+    #[cfg(test)]
+    mod csp_test;
+
     // https://github.com/libcsp/libcsp/blob/master/include/csp/csp_types.h
     //----------------------------------------------------------------
     //  Reserved Ports for CSP Service
-    enum csp_service_ports_t {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum csp_service_ports_t {
         CSP_CMP				= 0,   // !< CSP management, e.g. memory, routes, stats
         CSP_PING			= 1,   // !< Ping - return ping
         CSP_PS				= 2,   // !< Current process list
@@ -17,18 +29,80 @@ pub mod CSP {
         CSP_BUF_FREE		= 5,   // !< Free CSP buffers
         CSP_UPTIME			= 6,   // !< Uptime
     }
+
+    impl csp_service_ports_t {
+        /// Service reserved on `port`, `None` for user ports above CSP_UPTIME
+        pub fn from_port(port: u8) -> Option<csp_service_ports_t> {
+            use self::csp_service_ports_t::*;
+            match port {
+                0 => Some(CSP_CMP),
+                1 => Some(CSP_PING),
+                2 => Some(CSP_PS),
+                3 => Some(CSP_MEMFREE),
+                4 => Some(CSP_REBOOT),
+                5 => Some(CSP_BUF_FREE),
+                6 => Some(CSP_UPTIME),
+                _ => None,
+            }
+        }
+    }
+
+    impl From<csp_service_ports_t> for u8 {
+        fn from(port: csp_service_ports_t) -> u8 {
+            port as u8
+        }
+    }
+
+    impl fmt::Display for csp_service_ports_t {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            fmt::Debug::fmt(self, f)
+        }
+    }
+
     //----------------------------------------------------------------
     // listen on all prots
     pub const CSP_ANY:u8 = 255;
     //------------------------------
     // message priority
-    enum csp_prio_t {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum csp_prio_t {
         CSP_PRIO_CRITICAL		= 0, // !< Critical
         CSP_PRIO_HIGH			= 1, // !< High
+        #[default]
         CSP_PRIO_NORM			= 2, // !< Normal (default);
         CSP_PRIO_LOW			= 3, // !< Low
     }
 
+    impl TryFrom<u8> for csp_prio_t {
+        type Error = CspError;
+
+        fn try_from(prio: u8) -> Result<csp_prio_t, CspError> {
+            use self::csp_prio_t::*;
+            if prio as u32 > CSP_ID_PRIO_MAX {
+                return Err(CspError::InvalidPriority(prio));
+            }
+            match prio {
+                0 => Ok(CSP_PRIO_CRITICAL),
+                1 => Ok(CSP_PRIO_HIGH),
+                2 => Ok(CSP_PRIO_NORM),
+                3 => Ok(CSP_PRIO_LOW),
+                _ => Err(CspError::InvalidPriority(prio)),
+            }
+        }
+    }
+
+    impl From<csp_prio_t> for u8 {
+        fn from(prio: csp_prio_t) -> u8 {
+            prio as u8
+        }
+    }
+
+    impl fmt::Display for csp_prio_t {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            fmt::Debug::fmt(self, f)
+        }
+    }
+
     //--------------------------------------------------------------
     //FLAGS
     pub const CSP_ID_PRIO_SIZE:u8  =		0b0000_0010; // !< Bits for priority, see #csp_prio_t
@@ -56,6 +130,32 @@ pub mod CSP {
     pub const CSP_ID_FLAGS_MASK	  :u32=	(CSP_ID_FLAGS_MAX << (0));
     /** CSP identifier/header - connection mask (source & destination address + source & destination ports) */
     pub const CSP_ID_CONN_MASK	  :u32=	(CSP_ID_SRC_MASK | CSP_ID_DST_MASK | CSP_ID_DPORT_MASK | CSP_ID_SPORT_MASK);
+    // ---------------------------------------------------------------------------
+    // CSP 2.x header (48 bits), https://github.com/libcsp/libcsp/blob/develop/src/csp_id.c
+    pub const CSP_ID2_PRIO_SIZE:u8  =		2;  // !< Bits for priority
+    pub const CSP_ID2_HOST_SIZE:u8  =		14; // !< Bits for host (destination/source address)
+    pub const CSP_ID2_PORT_SIZE:u8  =		6;  // !< Bits for port (destination/source port)
+    pub const CSP_ID2_FLAGS_SIZE:u8 =		6;  // !< Bits for flags
+
+    pub const CSP_ID2_PRIO_MAX    :u64=	((1 << (CSP_ID2_PRIO_SIZE)) - 1);  // !< Max priority value in header
+    pub const CSP_ID2_HOST_MAX    :u64=	((1 << (CSP_ID2_HOST_SIZE)) - 1);  // !< Max host value in header
+    pub const CSP_ID2_PORT_MAX    :u64=	((1 << (CSP_ID2_PORT_SIZE)) - 1);  // !< Max port value in header
+    pub const CSP_ID2_FLAGS_MAX   :u64=	((1 << (CSP_ID2_FLAGS_SIZE)) - 1); // !< Max flag(s) value in header
+
+    /** CSP 2.x identifier/header - priority mask */
+    pub const CSP_ID2_PRIO_MASK   :u64=	(CSP_ID2_PRIO_MAX  << 46);
+    /** CSP 2.x identifier/header - destination address mask (dst comes before src in 2.x) */
+    pub const CSP_ID2_DST_MASK    :u64=	(CSP_ID2_HOST_MAX  << 32);
+    /** CSP 2.x identifier/header - source address mask */
+    pub const CSP_ID2_SRC_MASK    :u64=	(CSP_ID2_HOST_MAX  << 18);
+    /** CSP 2.x identifier/header - destination port mask */
+    pub const CSP_ID2_DPORT_MASK  :u64=	(CSP_ID2_PORT_MAX  << 12);
+    /** CSP 2.x identifier/header - source port mask */
+    pub const CSP_ID2_SPORT_MASK  :u64=	(CSP_ID2_PORT_MAX  << 6);
+    /** CSP 2.x identifier/header - flag mask */
+    pub const CSP_ID2_FLAGS_MASK  :u64=	(CSP_ID2_FLAGS_MAX);
+    /** CSP 2.x identifier/header - connection mask */
+    pub const CSP_ID2_CONN_MASK   :u64=	(CSP_ID2_SRC_MASK | CSP_ID2_DST_MASK | CSP_ID2_DPORT_MASK | CSP_ID2_SPORT_MASK);
     // ----------------------------------------------------------------
     // CSP HEADER FLAGS
     pub const CSP_FRES1:u32 = 0x80;
@@ -93,69 +193,369 @@ pub mod CSP {
     // ------------------------------
     // CSP Padding Bytes
     pub const CSP_PADDING_BYTES: usize = 10;
+    // ------------------------------
+    // Default payload size, matches libcsp's buffer_data_size
+    pub const CSP_BUFFER_SIZE: usize = 256;
+    // ------------------------------
+    // No via address, deliver straight to the destination
+    pub const CSP_NO_VIA_ADDRESS: u16 = 0xFFFF;
 
     
-    pub union csp_id_t {
-        
-        ext: u8,
-
-        // possible bugs from endian of type u8 (assuming big endian)
-        
-        //struct __attribute__((__packed__)) line 127 in csp_types.h
-        pri:u8,
-        src:u8,  
-        dst:u8,
-        dport:u8,
-        sport:u8,
-        flags:u8
-    }
+    // CSP identifier/header, see csp_id.rs
+    mod csp_id;
+    pub use self::csp_id::*;
 
-    pub struct csp_packet_t {
-        padding: [u8; CSP_PADDING_BYTES],
-        length: usize,
-        id: csp_id_t,
+    // preallocated packet buffers, see csp_buffer.rs
+    mod csp_buffer;
+    pub use self::csp_buffer::*;
+
+    // packet integrity and security, applied on send (csp_io.rs) and receive (csp_route.rs)
+    mod csp_crc32;
+    pub use self::csp_crc32::*;
+    mod csp_sha1;
+    pub use self::csp_sha1::*;
+    mod csp_hmac;
+    pub use self::csp_hmac::*;
+    mod csp_xtea;
+    pub use self::csp_xtea::*;
+
+    // network interfaces, see csp_interface.rs
+    mod csp_interface;
+    pub use self::csp_interface::*;
+    mod csp_if_lo;
+    pub use self::csp_if_lo::*;
+    mod csp_if_udp;
+    pub use self::csp_if_udp::*;
+    #[cfg(feature = "zmq")]
+    mod csp_if_zmqhub;
+    #[cfg(feature = "zmq")]
+    pub use self::csp_if_zmqhub::*;
+
+    // CAN fragmentation protocol, see csp_if_can.rs
+    mod csp_if_can;
+    pub use self::csp_if_can::*;
+    mod csp_can_socketcan;
+    pub use self::csp_can_socketcan::*;
 
-        // using box pointers to heap to have dynamically sized data packets
-        data8: Box<[u8]>,   // for can
-        data16: Box<[u16]>, // for uart
-        data32: Box<[u32]>, // for anything else (networking)
+    // KISS framing for serial links, see csp_if_kiss.rs
+    mod csp_if_kiss;
+    pub use self::csp_if_kiss::*;
+    mod csp_usart;
+    pub use self::csp_usart::*;
 
+    // interface list and routing table, see csp_iflist.rs and csp_rtable.rs
+    mod csp_iflist;
+    pub use self::csp_iflist::*;
+    mod csp_rtable;
+    pub use self::csp_rtable::*;
+
+    // node configuration and input queue, see csp_node.rs
+    mod csp_node;
+    pub use self::csp_node::*;
+    mod csp_conn;
+    pub use self::csp_conn::*;
+    mod csp_port;
+    pub use self::csp_port::*;
+    mod csp_rdp;
+    pub use self::csp_rdp::*;
+
+    // sending and incoming packet processing, see csp_io.rs and csp_route.rs
+    mod csp_io;
+    pub use self::csp_io::*;
+    mod csp_route;
+    pub use self::csp_route::*;
+
+    /// CSP packet: header plus one owned payload buffer.
+    ///
+    /// The buffer is allocated once with a fixed capacity and `length` is the
+    /// number of payload bytes in use; interfaces do their own framing.
+    /// Packets taken from a csp_buffer_pool_t give their buffer back when dropped.
+    pub struct csp_packet_t {
+        length: usize,
+        pub id: csp_id_t,
+        data: Box<[u8]>,
+        pool: Option<csp_buffer_pool_t>,
     }
 
     pub const CSP_REBOOT_MAGIC:u32 = 0x80078007;
     pub const CSP_REBOOT_SHUTDOWN_MAGIC:u32 = 0xD1E5529A;
 
-    // create csp_packet_t structs that have data split into [u8], [u16], [u32]
-    // depending on the destination ie (can, uart, internal network)
-    pub fn csp8u_CAN<T> (packet: csp_packet_t, data:T) -> Option<csp_packet_t> {
+    impl csp_packet_t {
+        /// Empty packet with room for `capacity` payload bytes
+        pub fn new(capacity: usize) -> csp_packet_t {
+            csp_packet_t {
+                length: 0,
+                id: csp_id_t::default(),
+                data: vec![0u8; capacity].into_boxed_slice(),
+                pool: None,
+            }
+        }
 
-    }
+        /// Packet holding a copy of `data`, with room for at least
+        /// CSP_BUFFER_SIZE payload bytes so trailers can be added to it
+        pub fn from_slice(id: csp_id_t, data: &[u8]) -> csp_packet_t {
+            let mut buf = vec![0u8; data.len().max(CSP_BUFFER_SIZE)];
+            buf[..data.len()].copy_from_slice(data);
+            csp_packet_t {
+                length: data.len(),
+                id,
+                data: buf.into_boxed_slice(),
+                pool: None,
+            }
+        }
 
-    pub fn csp16u_UART<T> (data:T) -> Option<csp_packet_t>{
+        /// Payload bytes in use
+        pub fn length(&self) -> usize {
+            self.length
+        }
 
-    }
+        /// Payload bytes the buffer can hold
+        pub fn capacity(&self) -> usize {
+            self.data.len()
+        }
 
-    impl csp_packet_t {
+        /// Payload in use
+        pub fn data(&self) -> &[u8] {
+            &self.data[..self.length]
+        }
+
+        pub fn data_mut(&mut self) -> &mut [u8] {
+            &mut self.data[..self.length]
+        }
+
+        /// Grow or shrink the payload. Bytes exposed by growing keep whatever
+        /// the buffer held. Fails if `length` exceeds the capacity.
+        pub fn set_length(&mut self, length: usize) -> Result<(), CspError> {
+            if length > self.capacity() {
+                return Err(CspError::TooLarge { length, max: self.capacity() });
+            }
+            self.length = length;
+            Ok(())
+        }
+
+        pub fn clear(&mut self) {
+            self.length = 0;
+        }
+
+        /// Copy `bytes` to the end of the payload
+        pub fn append(&mut self, bytes: &[u8]) -> Result<(), CspError> {
+            let offset = self.length;
+            self.write(offset, bytes)
+        }
+
+        /// Copy `bytes` into the payload at `offset`, extending `length` if the
+        /// write goes past it. Fails if it would not fit in the buffer.
+        pub fn write(&mut self, offset: usize, bytes: &[u8]) -> Result<(), CspError> {
+            let end = offset.saturating_add(bytes.len());
+            if end > self.capacity() {
+                return Err(CspError::TooLarge { length: end, max: self.capacity() });
+            }
+            self.data[offset..end].copy_from_slice(bytes);
+            if end > self.length {
+                self.length = end;
+            }
+            Ok(())
+        }
+
+        /// Borrow `len` payload bytes at `offset`, `None` if past `length`
+        pub fn read(&self, offset: usize, len: usize) -> Option<&[u8]> {
+            let end = offset.checked_add(len)?;
+            self.data().get(offset..end)
+        }
+
+        // typed big endian accessors, CSP payloads are network byte order
+        pub fn read_u8(&self, offset: usize) -> Option<u8> {
+            self.data().get(offset).copied()
+        }
+
+        pub fn read_u16(&self, offset: usize) -> Option<u16> {
+            let mut raw = [0u8; 2];
+            raw.copy_from_slice(self.read(offset, 2)?);
+            Some(u16::from_be_bytes(raw))
+        }
+
+        pub fn read_u32(&self, offset: usize) -> Option<u32> {
+            let mut raw = [0u8; 4];
+            raw.copy_from_slice(self.read(offset, 4)?);
+            Some(u32::from_be_bytes(raw))
+        }
+
+        pub fn read_f32(&self, offset: usize) -> Option<f32> {
+            self.read_u32(offset).map(f32::from_bits)
+        }
+
+        pub fn write_u8(&mut self, offset: usize, value: u8) -> Result<(), CspError> {
+            self.write(offset, &[value])
+        }
+
+        pub fn write_u16(&mut self, offset: usize, value: u16) -> Result<(), CspError> {
+            self.write(offset, &value.to_be_bytes())
+        }
+
+        pub fn write_u32(&mut self, offset: usize, value: u32) -> Result<(), CspError> {
+            self.write(offset, &value.to_be_bytes())
+        }
+
+        pub fn write_f32(&mut self, offset: usize, value: f32) -> Result<(), CspError> {
+            self.write_u32(offset, value.to_bits())
+        }
+
+        /// Header followed by payload, the raw packet format used by UDP/ZMQ/KISS
+        pub fn to_bytes(&self, version: csp_version_t) -> Result<Vec<u8>, CspError> {
+            let size = version.header_size();
+            let mut raw = vec![0u8; size + self.length];
+            self.id.encode(version, &mut raw)?;
+            raw[size..].copy_from_slice(self.data());
+            Ok(raw)
+        }
 
-        pub fn send_CAN(cansocket: socketcan::CANSocket){
+        /// Parse header followed by payload, fails if shorter than the header
+        pub fn from_bytes(version: csp_version_t, raw: &[u8]) -> Result<csp_packet_t, CspError> {
+            let id = csp_id_t::decode(version, raw)?;
+            Ok(csp_packet_t::from_slice(id, &raw[version.header_size()..]))
+        }
 
+        pub fn to_json(&self) -> json::JsonValue {
+            json::object! {
+                "pri" => u8::from(self.id.pri),
+                "src" => self.id.src,
+                "dst" => self.id.dst,
+                "dport" => self.id.dport,
+                "sport" => self.id.sport,
+                "flags" => self.id.flags,
+                "length" => self.length,
+                "data" => self.data().to_vec(),
+            }
         }
+    }
 
-        pub fn send_UART(packet: csp_packet_t){
+    #[cfg(test)]
+    mod tests {
+        use super::*;
 
+        #[test]
+        fn service_ports() {
+            use self::csp_service_ports_t::*;
+            let services = [CSP_CMP, CSP_PING, CSP_PS, CSP_MEMFREE, CSP_REBOOT, CSP_BUF_FREE, CSP_UPTIME];
+            for (port, &service) in services.iter().enumerate() {
+                assert_eq!(csp_service_ports_t::from_port(port as u8), Some(service));
+                assert_eq!(u8::from(service), port as u8);
+            }
+            // user ports are valid ports, just not services
+            for port in 7..=CSP_ID_PORT_MAX as u8 {
+                assert_eq!(csp_service_ports_t::from_port(port), None);
+            }
+            assert_eq!(csp_service_ports_t::from_port(CSP_ANY), None);
+            assert_eq!(CSP_BUF_FREE.to_string(), "CSP_BUF_FREE");
         }
 
-        pub fn recv_CAN() -> Result<csp_packet_t>{
-            
+        #[test]
+        fn priorities() {
+            use self::csp_prio_t::*;
+            for (prio, &expected) in [CSP_PRIO_CRITICAL, CSP_PRIO_HIGH, CSP_PRIO_NORM, CSP_PRIO_LOW].iter().enumerate() {
+                assert_eq!(csp_prio_t::try_from(prio as u8).unwrap(), expected);
+                assert_eq!(u8::from(expected), prio as u8);
+            }
+            for &prio in &[4, 5, u8::MAX] {
+                assert!(matches!(csp_prio_t::try_from(prio), Err(CspError::InvalidPriority(p)) if p == prio));
+            }
+            assert_eq!(csp_prio_t::default(), CSP_PRIO_NORM);
+            assert_eq!(CSP_PRIO_LOW.to_string(), "CSP_PRIO_LOW");
         }
 
-        pub fn recv_uart() -> Result<csp_packet_t>{
+        #[test]
+        fn packet_accessors() {
+            let mut packet = csp_packet_t::new(16);
+            assert_eq!((packet.length(), packet.capacity()), (0, 16));
+            packet.write_u8(0, 0xAB).unwrap();
+            packet.write_u16(1, 0x1234).unwrap();
+            packet.write_u32(3, 0xDEAD_BEEF).unwrap();
+            packet.write_f32(7, -1.5).unwrap();
+            assert_eq!(packet.length(), 11);
+            // network byte order
+            assert_eq!(packet.data(), &[0xAB, 0x12, 0x34, 0xDE, 0xAD, 0xBE, 0xEF, 0xBF, 0xC0, 0x00, 0x00]);
+            assert_eq!(packet.read_u8(0), Some(0xAB));
+            assert_eq!(packet.read_u16(1), Some(0x1234));
+            assert_eq!(packet.read_u32(3), Some(0xDEAD_BEEF));
+            assert_eq!(packet.read_f32(7), Some(-1.5));
+
+            // reads stop at length, not capacity
+            assert_eq!(packet.read_u8(10), Some(0));
+            assert_eq!(packet.read_u8(11), None);
+            assert_eq!(packet.read_u16(10), None);
+            assert_eq!(packet.read_u32(8), None);
+            assert_eq!(packet.read_f32(usize::MAX), None);
+            assert_eq!(packet.read(0, usize::MAX), None);
+            assert_eq!(packet.read(11, 0), Some(&[][..]));
 
+            // overwriting inside the payload keeps the length
+            packet.write_u16(0, 0xFFFF).unwrap();
+            assert_eq!(packet.length(), 11);
         }
 
-        pub fn to_json(packet: csp_packet_t) {
+        #[test]
+        fn packet_capacity() {
+            let mut packet = csp_packet_t::new(8);
+            packet.append(b"1234").unwrap();
+            packet.append(b"5678").unwrap();
+            assert_eq!(packet.data(), b"12345678");
+            assert!(matches!(packet.append(b"9"), Err(CspError::TooLarge { length: 9, max: 8 })));
+            packet.append(b"").unwrap();
+
+            // writes past capacity fail and change nothing
+            assert!(matches!(packet.write_u32(5, 0), Err(CspError::TooLarge { length: 9, max: 8 })));
+            assert!(packet.write_u16(usize::MAX, 0).is_err());
+            packet.write_u32(4, 0x3132_3334).unwrap();
+            assert_eq!(packet.data(), b"12341234");
+
+            assert!(matches!(packet.set_length(9), Err(CspError::TooLarge { length: 9, max: 8 })));
+            packet.set_length(2).unwrap();
+            assert_eq!(packet.data(), b"12");
+            // growing again exposes what the buffer held
+            packet.set_length(8).unwrap();
+            assert_eq!(packet.data(), b"12341234");
+            packet.clear();
+            assert_eq!((packet.length(), packet.capacity()), (0, 8));
+
+            // writing past length grows it to the end of the write
+            packet.write_u8(7, b'x').unwrap();
+            assert_eq!(packet.length(), 8);
+            assert_eq!(packet.read_u8(7), Some(b'x'));
 
+            let mut empty = csp_packet_t::new(0);
+            assert!(empty.append(b"x").is_err());
+            empty.set_length(0).unwrap();
+            assert!(empty.data().is_empty());
         }
 
+        #[test]
+        fn packet_bytes() {
+            let id = csp_id_t { pri: csp_prio_t::CSP_PRIO_HIGH, src: 1, dst: 2, dport: 10, sport: 20, flags: CSP_FCRC32 as u8 };
+            let packet = csp_packet_t::from_slice(id, b"payload");
+            assert_eq!(packet.capacity(), CSP_BUFFER_SIZE);
+            let full = csp_packet_t::from_slice(id, &vec![7u8; CSP_BUFFER_SIZE + 1]);
+            assert_eq!(full.capacity(), CSP_BUFFER_SIZE + 1);
+
+            for &version in &[csp_version_t::CSP_VERSION_1, csp_version_t::CSP_VERSION_2] {
+                let raw = packet.to_bytes(version).unwrap();
+                assert_eq!(raw.len(), version.header_size() + 7);
+                assert_eq!(&raw[version.header_size()..], b"payload");
+                let decoded = csp_packet_t::from_bytes(version, &raw).unwrap();
+                assert_eq!((decoded.id, decoded.data()), (id, &b"payload"[..]));
+
+                // a bare header is an empty packet, less is no packet
+                let header = &raw[..version.header_size()];
+                assert_eq!(csp_packet_t::from_bytes(version, header).unwrap().length(), 0);
+                assert!(csp_packet_t::from_bytes(version, &header[..header.len() - 1]).is_err());
+
+                let raw = full.to_bytes(version).unwrap();
+                assert_eq!(csp_packet_t::from_bytes(version, &raw).unwrap().data(), full.data());
+            }
+
+            // addresses that do not fit a v1 header
+            let wide = csp_packet_t::from_slice(csp_id_t { dst: 100, ..id }, b"");
+            assert!(wide.to_bytes(csp_version_t::CSP_VERSION_1).is_err());
+            assert!(wide.to_bytes(csp_version_t::CSP_VERSION_2).is_ok());
+        }
     }
 }